@@ -0,0 +1,273 @@
+use assembler::{command_type, split_lines, Code, Command, Parser, SourceLine, SymbolTable};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, Diagnostic, DiagnosticSeverity, DocumentSymbol, Position,
+    Range, SymbolKind,
+};
+use std::collections::HashMap;
+
+/// Largest value an A-instruction can load (15 bits).
+pub const MAX_CONSTANT: u32 = 32767;
+
+/// A label definition `(NAME)` and the source range of its name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub name: String,
+    pub range: Range,
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.$:".contains(c)
+}
+
+/// LSP positions count UTF-16 code units, so this converts a byte offset
+/// into `raw` to a column.
+fn utf16_column(raw: &str, byte: usize) -> u32 {
+    raw[..byte].encode_utf16().count() as u32
+}
+
+/// The byte offset of a UTF-16 column, clamped to the end of `raw`.
+fn byte_offset(raw: &str, column: u32) -> usize {
+    let mut units = 0;
+    for (offset, c) in raw.char_indices() {
+        if units >= column as usize {
+            return offset;
+        }
+        units += c.len_utf16();
+    }
+    raw.len()
+}
+
+/// Range on line `line` between two byte offsets into its text `raw`.
+fn line_range(raw: &str, line: usize, start: usize, end: usize) -> Range {
+    Range::new(
+        Position::new(line as u32, utf16_column(raw, start)),
+        Position::new(line as u32, utf16_column(raw, end)),
+    )
+}
+
+/// Range covering the instruction of a line, without indentation or comment.
+fn command_range(raw: &str, line: &SourceLine) -> Range {
+    let start = raw.len() - raw.trim_start().len();
+    line_range(raw, line.number - 1, start, start + line.command.len())
+}
+
+fn raw_lines(text: &str) -> Vec<&str> {
+    text.lines().collect()
+}
+
+/// Collects every label definition in source order.
+pub fn labels(text: &str) -> Vec<Label> {
+    let raw = raw_lines(text);
+    split_lines(text)
+        .iter()
+        .filter(|line| matches!(command_type(&line.command), Ok(Command::LCommand)))
+        .filter_map(|line| {
+            let name = SymbolTable::parse_symbol(line.command.clone(), Command::LCommand).ok()?;
+            let text = raw[line.number - 1];
+            let start = text.find(&name)?;
+            Some(Label {
+                range: line_range(text, line.number - 1, start, start + name.len()),
+                name,
+            })
+        })
+        .collect()
+}
+
+/// Reports every line the assembler would reject.
+pub fn diagnostics(text: &str) -> Vec<Diagnostic> {
+    let raw = raw_lines(text);
+    let code = Code::new();
+    let mut defined: HashMap<String, usize> = HashMap::new();
+    let mut diagnostics = Vec::new();
+
+    for line in split_lines(text)
+        .iter()
+        .filter(|line| !line.command.is_empty())
+    {
+        let message = match command_type(&line.command) {
+            Ok(Command::CCommand) => code
+                .parse(line.command.clone())
+                .err()
+                .map(|e| e.to_string()),
+            Ok(command_type @ Command::ACommand) => {
+                match SymbolTable::parse_symbol(line.command.clone(), command_type) {
+                    Ok(symbol) => match symbol.parse::<u32>() {
+                        Ok(value) if value > MAX_CONSTANT => Some(format!(
+                            "Constant {} does not fit in 15 bits (max {}).",
+                            value, MAX_CONSTANT
+                        )),
+                        _ => None,
+                    },
+                    Err(e) => Some(e.to_string()),
+                }
+            }
+            Ok(command_type @ Command::LCommand) => {
+                match SymbolTable::parse_symbol(line.command.clone(), command_type) {
+                    Ok(symbol) => match defined.get(&symbol) {
+                        Some(first) => Some(format!(
+                            "Label `{}` is already defined on line {}.",
+                            symbol, first
+                        )),
+                        None => {
+                            defined.insert(symbol, line.number);
+                            None
+                        }
+                    },
+                    Err(e) => Some(e.to_string()),
+                }
+            }
+            Err(e) => Some(format!("{} `{}`", e, line.command)),
+        };
+        if let Some(message) = message {
+            diagnostics.push(Diagnostic {
+                range: command_range(raw[line.number - 1], line),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some(String::from("hack-asm")),
+                message,
+                ..Diagnostic::default()
+            });
+        }
+    }
+    diagnostics
+}
+
+/// Returns the symbol of an A- or L-instruction under the cursor.
+pub fn symbol_at(text: &str, position: Position) -> Option<(String, Range)> {
+    let raw = raw_lines(text).get(position.line as usize).cloned()?;
+    let command = raw.split("//").next()?.trim();
+    if !command.starts_with('@') && !command.starts_with('(') {
+        return None;
+    }
+
+    // symbols are ASCII, so scanning bytes never splits a character
+    let bytes = raw.as_bytes();
+    let cursor = byte_offset(raw, position.character);
+    let mut start = cursor;
+    while start > 0 && is_symbol_char(bytes[start - 1] as char) {
+        start -= 1;
+    }
+    let mut end = cursor;
+    while end < bytes.len() && is_symbol_char(bytes[end] as char) {
+        end += 1;
+    }
+    if start == end {
+        return None;
+    }
+    let symbol = raw[start..end].to_string();
+    Some((symbol, line_range(raw, position.line as usize, start, end)))
+}
+
+/// Resolves the label under the cursor to the range of its definition.
+pub fn definition(text: &str, position: Position) -> Option<Range> {
+    let (symbol, _) = symbol_at(text, position)?;
+    labels(text)
+        .into_iter()
+        .find(|label| label.name == symbol)
+        .map(|label| label.range)
+}
+
+/// Resolves all symbols the same way the assembler does, as far as the
+/// program can be assembled.
+pub fn resolve_symbols(text: &str) -> HashMap<String, u32> {
+    let mut parser = Parser::new(text);
+    let _ = parser.to_bytes();
+    parser.symbol_table().symbol_map().clone()
+}
+
+/// Describes the symbol under the cursor together with its address.
+pub fn hover(text: &str, position: Position) -> Option<(String, Range)> {
+    let (symbol, range) = symbol_at(text, position)?;
+    if symbol.parse::<u32>().is_ok() {
+        return None;
+    }
+    let address = *resolve_symbols(text).get(&symbol)?;
+    let description = if labels(text).iter().any(|label| label.name == symbol) {
+        format!("`{}`: label, ROM address {}", symbol, address)
+    } else if SymbolTable::new().symbol_map().contains_key(&symbol) {
+        format!("`{}`: predefined symbol, RAM address {}", symbol, address)
+    } else {
+        format!("`{}`: variable, RAM address {}", symbol, address)
+    };
+    Some((description, range))
+}
+
+fn completion_item(label: &str, kind: CompletionItemKind, detail: &str) -> CompletionItem {
+    CompletionItem {
+        label: label.to_string(),
+        kind: Some(kind),
+        detail: Some(detail.to_string()),
+        ..CompletionItem::default()
+    }
+}
+
+/// Offers mnemonics or symbols depending on what precedes the cursor.
+pub fn completions(text: &str, position: Position) -> Vec<CompletionItem> {
+    let code = Code::new();
+    let raw = raw_lines(text)
+        .get(position.line as usize)
+        .cloned()
+        .unwrap_or("");
+    let prefix = raw[..byte_offset(raw, position.character)].trim_start();
+
+    if prefix.starts_with('@') {
+        let labels: Vec<String> = labels(text).into_iter().map(|label| label.name).collect();
+        let mut symbols: Vec<(String, u32)> = resolve_symbols(text).into_iter().collect();
+        symbols.sort();
+        return symbols
+            .iter()
+            .map(|(symbol, address)| {
+                if labels.contains(symbol) {
+                    let detail = format!("ROM {}", address);
+                    completion_item(symbol, CompletionItemKind::REFERENCE, &detail)
+                } else {
+                    let detail = format!("RAM {}", address);
+                    completion_item(symbol, CompletionItemKind::VARIABLE, &detail)
+                }
+            })
+            .collect();
+    }
+    if prefix.contains(';') {
+        return code
+            .jump_mnemonics()
+            .iter()
+            .map(|jump| completion_item(jump, CompletionItemKind::KEYWORD, "jump"))
+            .collect();
+    }
+    if prefix.contains('=') {
+        return code
+            .comp_mnemonics()
+            .iter()
+            .map(|comp| completion_item(comp, CompletionItemKind::KEYWORD, "comp"))
+            .collect();
+    }
+
+    let dests = code
+        .dest_mnemonics()
+        .into_iter()
+        .map(|dest| completion_item(&format!("{}=", dest), CompletionItemKind::KEYWORD, "dest"));
+    let comps = code
+        .comp_mnemonics()
+        .into_iter()
+        .map(|comp| completion_item(comp, CompletionItemKind::KEYWORD, "comp"));
+    dests.chain(comps).collect()
+}
+
+/// Lists all labels as document symbols.
+pub fn document_symbols(text: &str) -> Vec<DocumentSymbol> {
+    labels(text)
+        .into_iter()
+        .map(|label| {
+            #[allow(deprecated)]
+            DocumentSymbol {
+                name: label.name,
+                detail: None,
+                kind: SymbolKind::FUNCTION,
+                tags: None,
+                deprecated: None,
+                range: label.range,
+                selection_range: label.range,
+                children: None,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::process;
+
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _,
+};
+use lsp_types::{
+    CompletionOptions, CompletionParams, CompletionResponse, DocumentSymbolParams,
+    DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents,
+    HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf,
+    PublishDiagnosticsParams, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
+    Url,
+};
+
+type BoxResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Language server error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> BoxResult<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![
+                String::from("@"),
+                String::from("="),
+                String::from(";"),
+            ]),
+            ..CompletionOptions::default()
+        }),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    main_loop(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+fn main_loop(connection: Connection) -> BoxResult<()> {
+    // full text of every open document, the client sends complete contents
+    let mut documents: HashMap<Url, String> = HashMap::new();
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                let response = handle_request(&documents, request)?;
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                if let Some((uri, text)) = handle_notification(&mut documents, notification)? {
+                    publish_diagnostics(&connection, uri, &text)?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn handle_request(documents: &HashMap<Url, String>, request: Request) -> BoxResult<Response> {
+    let id = request.id.clone();
+    let result = match request.method.as_str() {
+        GotoDefinition::METHOD => {
+            let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+            let position = params.text_document_position_params;
+            let uri = position.text_document.uri;
+            let cursor = position.position;
+            let range = documents
+                .get(&uri)
+                .and_then(|text| asm_lsp::definition(text, cursor));
+            serde_json::to_value(
+                range.map(|range| GotoDefinitionResponse::Scalar(Location::new(uri, range))),
+            )?
+        }
+        HoverRequest::METHOD => {
+            let params: HoverParams = serde_json::from_value(request.params)?;
+            let position = params.text_document_position_params;
+            let hover = documents
+                .get(&position.text_document.uri)
+                .and_then(|text| asm_lsp::hover(text, position.position))
+                .map(|(value, range)| Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value,
+                    }),
+                    range: Some(range),
+                });
+            serde_json::to_value(hover)?
+        }
+        Completion::METHOD => {
+            let params: CompletionParams = serde_json::from_value(request.params)?;
+            let position = params.text_document_position;
+            let items = documents
+                .get(&position.text_document.uri)
+                .map(|text| asm_lsp::completions(text, position.position))
+                .unwrap_or_default();
+            serde_json::to_value(CompletionResponse::Array(items))?
+        }
+        DocumentSymbolRequest::METHOD => {
+            let params: DocumentSymbolParams = serde_json::from_value(request.params)?;
+            let symbols = documents
+                .get(&params.text_document.uri)
+                .map(|text| asm_lsp::document_symbols(text))
+                .unwrap_or_default();
+            serde_json::to_value(DocumentSymbolResponse::Nested(symbols))?
+        }
+        _ => return Ok(method_not_found(id, &request.method)),
+    };
+    Ok(Response::new_ok(id, result))
+}
+
+fn method_not_found(id: RequestId, method: &str) -> Response {
+    Response::new_err(
+        id,
+        lsp_server::ErrorCode::MethodNotFound as i32,
+        format!("Unsupported request `{}`.", method),
+    )
+}
+
+/// Updates the open documents and returns the one whose diagnostics changed.
+fn handle_notification(
+    documents: &mut HashMap<Url, String>,
+    notification: Notification,
+) -> BoxResult<Option<(Url, String)>> {
+    match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: lsp_types::DidOpenTextDocumentParams =
+                serde_json::from_value(notification.params)?;
+            let document = params.text_document;
+            documents.insert(document.uri.clone(), document.text.clone());
+            Ok(Some((document.uri, document.text)))
+        }
+        DidChangeTextDocument::METHOD => {
+            let params: lsp_types::DidChangeTextDocumentParams =
+                serde_json::from_value(notification.params)?;
+            let uri = params.text_document.uri;
+            match params.content_changes.into_iter().last() {
+                Some(change) => {
+                    documents.insert(uri.clone(), change.text.clone());
+                    Ok(Some((uri, change.text)))
+                }
+                None => Ok(None),
+            }
+        }
+        DidCloseTextDocument::METHOD => {
+            let params: lsp_types::DidCloseTextDocumentParams =
+                serde_json::from_value(notification.params)?;
+            documents.remove(&params.text_document.uri);
+            // an empty document clears the diagnostics of the closed file
+            Ok(Some((params.text_document.uri, String::new())))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn publish_diagnostics(connection: &Connection, uri: Url, text: &str) -> BoxResult<()> {
+    let params = PublishDiagnosticsParams::new(uri, asm_lsp::diagnostics(text), None);
+    let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+    connection
+        .sender
+        .send(Message::Notification(notification))?;
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use asm_lsp::{completions, definition, diagnostics, document_symbols, hover, labels};
+use lsp_types::{Position, Range};
+
+const INPUT: &str = r#"// Draws a line
+   @counter
+   M=D
+(LOOP)
+   @LOOP
+   D;JGT            // loop
+   @SCREEN
+   D=Z
+   @40000
+(LOOP)
+"#;
+
+#[test]
+fn test_labels() {
+    let labels = labels(INPUT);
+    assert_eq!(2, labels.len());
+    assert_eq!("LOOP", labels[0].name);
+    assert_eq!(
+        Range::new(Position::new(3, 1), Position::new(3, 5)),
+        labels[0].range
+    );
+}
+
+#[test]
+fn test_diagnostics() {
+    let diagnostics = diagnostics(INPUT);
+    let lines: Vec<u32> = diagnostics.iter().map(|d| d.range.start.line).collect();
+    assert_eq!(vec![7, 8, 9], lines);
+    assert_eq!(
+        Range::new(Position::new(7, 3), Position::new(7, 6)),
+        diagnostics[0].range
+    );
+    assert!(diagnostics[2].message.contains("already defined on line 4"));
+}
+
+#[test]
+fn test_definition() {
+    assert_eq!(
+        Some(Range::new(Position::new(3, 1), Position::new(3, 5))),
+        definition(INPUT, Position::new(4, 5))
+    );
+    assert_eq!(None, definition(INPUT, Position::new(1, 5)));
+}
+
+#[test]
+fn test_hover() {
+    let valid = "   @counter\n   M=D\n(LOOP)\n   @LOOP\n   0;JMP\n   @KBD\n";
+    let (text, _) = hover(valid, Position::new(0, 6)).unwrap();
+    assert_eq!("`counter`: variable, RAM address 16", text);
+    let (text, _) = hover(valid, Position::new(3, 5)).unwrap();
+    assert_eq!("`LOOP`: label, ROM address 2", text);
+    let (text, _) = hover(valid, Position::new(5, 4)).unwrap();
+    assert_eq!("`KBD`: predefined symbol, RAM address 24576", text);
+    assert_eq!(None, hover(valid, Position::new(1, 4)));
+}
+
+#[test]
+fn test_completions() {
+    let labels: Vec<String> = completions("D;J\n", Position::new(0, 3))
+        .into_iter()
+        .map(|item| item.label)
+        .collect();
+    assert_eq!(
+        vec!["JEQ", "JGE", "JGT", "JLE", "JLT", "JMP", "JNE"],
+        labels
+    );
+    let labels: Vec<String> = completions("D=\n", Position::new(0, 2))
+        .into_iter()
+        .map(|item| item.label)
+        .collect();
+    assert!(labels.contains(&String::from("D+M")));
+    let labels: Vec<String> = completions("(END)\n@\n", Position::new(1, 1))
+        .into_iter()
+        .map(|item| item.label)
+        .collect();
+    assert!(labels.contains(&String::from("END")));
+    assert!(labels.contains(&String::from("SCREEN")));
+}
+
+#[test]
+fn test_document_symbols() {
+    let symbols = document_symbols(INPUT);
+    assert_eq!(2, symbols.len());
+    assert_eq!("LOOP", symbols[0].name);
+}
+
+#[test]
+fn test_utf16_positions() {
+    // the ideographic space is 3 bytes but a single UTF-16 code unit
+    let text = "\u{3000}(LOOP) // ループ\n\u{3000}@LOOP\n\u{3000}D=A+M // ü\n\u{3000}0;\n";
+    let loop_range = Range::new(Position::new(0, 2), Position::new(0, 6));
+    assert_eq!(loop_range, labels(text)[0].range);
+    assert_eq!(Some(loop_range), definition(text, Position::new(1, 3)));
+    let (description, range) = hover(text, Position::new(1, 3)).unwrap();
+    assert_eq!("`LOOP`: label, ROM address 0", description);
+    assert_eq!(Range::new(Position::new(1, 2), Position::new(1, 6)), range);
+    assert_eq!(
+        Range::new(Position::new(2, 1), Position::new(2, 6)),
+        diagnostics(text)[0].range
+    );
+    let labels: Vec<String> = completions(text, Position::new(3, 3))
+        .into_iter()
+        .map(|item| item.label)
+        .collect();
+    assert!(labels.contains(&String::from("JMP")));
+}
@@ -0,0 +1,42 @@
+use std::env;
+use std::process;
+
+use assembler::format::format;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let check = args.iter().any(|arg| arg == "--check");
+    let filenames: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if filenames.is_empty() {
+        println!("Usage: asmfmt [--check] FILE.asm...");
+        process::exit(1);
+    }
+
+    let mut unformatted = 0;
+    for filename in filenames {
+        let content = assembler::read_file(filename).unwrap_or_else(|err| {
+            println!("Application error: {}", err);
+            process::exit(1);
+        });
+        let formatted = format(&content).unwrap_or_else(|err| {
+            println!("{}: {}", filename, err);
+            process::exit(1);
+        });
+        if formatted == content {
+            continue;
+        }
+
+        if check {
+            // only report, the review workflow decides what to do with it
+            println!("{} is not formatted", filename);
+            unformatted += 1;
+        } else if let Err(err) = assembler::write_file(filename, &formatted) {
+            println!("Failed writing file: {}", err);
+            process::exit(1);
+        }
+    }
+
+    if unformatted > 0 {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{command_type, split_lines, BoxResult, Code, Command, SourceLine, SymbolTable};
+
+/// Indentation of every instruction; labels stay flush left.
+pub const INDENT: &str = "   ";
+/// Column at which inline comments start.
+pub const COMMENT_COLUMN: usize = 20;
+
+/// Rewrites Hack assembly into the canonical layout: labels flush left,
+/// instructions indented, inline comments aligned to `COMMENT_COLUMN` and
+/// C-instructions spelled the way `Code` expects them.
+pub fn format(input: &str) -> BoxResult<String> {
+    let code = Code::new();
+    let lines = split_lines(input);
+    let mut formatted: Vec<String> = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        if line.is_blank() {
+            // collapse runs of blank lines and drop leading ones
+            if formatted.last().is_some_and(|last| !last.is_empty()) {
+                formatted.push(String::new());
+            }
+            continue;
+        }
+
+        let text = if line.command.is_empty() {
+            // a comment block directly above an instruction is indented with it
+            if followed_by_instruction(&lines[i + 1..]) {
+                String::from(INDENT)
+            } else {
+                String::new()
+            }
+        } else {
+            format_command(&code, &line.command)
+                .map_err(|e| format!("line {}: {}", line.number, e))?
+        };
+
+        let formatted_line = match &line.comment {
+            Some(comment) if line.command.is_empty() => format!("{}//{}", text, comment),
+            Some(comment) => {
+                let width = if text.len() + 2 > COMMENT_COLUMN {
+                    text.len() + 2
+                } else {
+                    COMMENT_COLUMN
+                };
+                format!("{:<width$}//{}", text, comment, width = width)
+            }
+            None => text,
+        };
+        formatted.push(formatted_line);
+    }
+
+    while formatted.last().is_some_and(|last| last.is_empty()) {
+        formatted.pop();
+    }
+    Ok(formatted
+        .iter()
+        .fold(String::new(), |acc, line| acc + line + "\n"))
+}
+
+fn followed_by_instruction(lines: &[SourceLine]) -> bool {
+    lines
+        .iter()
+        .find(|line| line.is_blank() || !line.command.is_empty())
+        .is_some_and(|line| !line.command.is_empty() && !line.command.starts_with('('))
+}
+
+fn format_command(code: &Code, command: &str) -> BoxResult<String> {
+    let compact: String = command.chars().filter(|c| !c.is_whitespace()).collect();
+    match command_type(&compact)? {
+        command_type @ Command::ACommand => {
+            let address = format!(
+                "@{}",
+                SymbolTable::parse_symbol(compact.clone(), command_type)?
+            );
+            if address != compact {
+                bail!("Invalid address `{}`.", command);
+            }
+            Ok(format!("{}{}", INDENT, address))
+        }
+        command_type @ Command::LCommand => {
+            let label = format!(
+                "({})",
+                SymbolTable::parse_symbol(compact.clone(), command_type)?
+            );
+            if label != compact {
+                bail!("Invalid label `{}`.", command);
+            }
+            Ok(label)
+        }
+        Command::CCommand => Ok(format!("{}{}", INDENT, code.normalize(&compact)?)),
+    }
+}
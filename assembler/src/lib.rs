@@ -9,12 +9,15 @@ use std::fmt;
 use std::fs;
 use std::io::{BufRead, Cursor};
 
+pub mod format;
+pub mod verify;
+
 type BoxResult<T> = Result<T, Box<dyn Error>>;
 
 lazy_static! {
-    static ref A_REGEX: Regex = Regex::new(r"@(?P<symbol>(\d+|(\w+\.?\w*\$?\w*)))").unwrap();
+    static ref A_REGEX: Regex = Regex::new(r"@(?P<symbol>(\d+|[A-Za-z_.$:][\w.$:]*))").unwrap();
     static ref C_REGEX: Regex = Regex::new(r"((?P<dest>[AMD]{1,3})=(?P<comp1>(\d+|\w+)?[-!+&|]?(\d+|\w+)?))|((?P<comp2>(\d+|\w+)?[-!+&|]?(\d+|\w+)?);(?P<jump>(JGT|JEQ|JGE|JLT|JNE|JLE|JMP)))").unwrap();
-    static ref L_REGEX: Regex = Regex::new(r"\((?P<symbol>\w+\.?\w*\$?\w*)\)").unwrap();
+    static ref L_REGEX: Regex = Regex::new(r"\((?P<symbol>[A-Za-z_.$:][\w.$:]*)\)").unwrap();
 }
 
 #[derive(Debug, PartialEq)]
@@ -135,6 +138,58 @@ impl<'a> Code<'a> {
         self.jump_map.get(code).unwrap_or(&"")
     }
 
+    fn is_comp(&self, code: &str) -> bool {
+        self.a_comp_map.contains_key(code) || self.m_comp_map.contains_key(code)
+    }
+
+    /// Rewrites a C-instruction into the spelling used by the lookup tables,
+    /// e.g. `DM = A + D` becomes `MD=D+A`.
+    pub fn normalize(&self, instruction: &str) -> BoxResult<String> {
+        let instruction: String = instruction.chars().filter(|c| !c.is_whitespace()).collect();
+        let (dest, rest) = match instruction.find('=') {
+            Some(pos) => (&instruction[..pos], &instruction[pos + 1..]),
+            None => ("", &instruction[..]),
+        };
+        let (comp, jump) = match rest.find(';') {
+            Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+            None => (rest, ""),
+        };
+        if !dest.is_empty() && !jump.is_empty() {
+            bail!("Cannot contain both destination and jump.");
+        }
+
+        // registers are always listed in A, M, D order
+        let ordered_dest: String = "AMD".chars().filter(|&c| dest.contains(c)).collect();
+        if ordered_dest.len() != dest.len() || !self.dest_map.contains_key(ordered_dest.as_str()) {
+            bail!("Unknown destination `{}`.", dest);
+        }
+        if !self.jump_map.contains_key(jump) {
+            bail!("Unknown jump `{}`.", jump);
+        }
+
+        let mut comp = comp.to_string();
+        if !self.is_comp(&comp) {
+            // commutative operations may be written with swapped operands
+            let chars: Vec<char> = comp.chars().collect();
+            if chars.len() == 3 && "+&|".contains(chars[1]) {
+                let swapped: String = [chars[2], chars[1], chars[0]].iter().collect();
+                if self.is_comp(&swapped) {
+                    comp = swapped;
+                }
+            }
+        }
+        if !self.is_comp(&comp) {
+            bail!("Unknown computation `{}`.", comp);
+        }
+
+        let normalized = match (ordered_dest.as_str(), jump) {
+            ("", "") => bail!("Missing destination or jump."),
+            ("", jump) => format!("{};{}", comp, jump),
+            (dest, _) => format!("{}={}", dest, comp),
+        };
+        Ok(normalized)
+    }
+
     pub fn parse(&self, instruction: String) -> BoxResult<String> {
         if instruction.contains(';') && instruction.contains('=') {
             bail!("Cannot contain both destination and jump.");
@@ -161,12 +216,95 @@ impl<'a> Code<'a> {
                 if let Some(j) = x.name("jump") {
                     jump = self.get_jump(j.as_str());
                 };
+                // the lookups return an empty string for unknown mnemonics
+                if comp.is_empty() {
+                    bail!("Unknown computation in `{}`.", instruction.trim());
+                }
+                if dest.is_empty() {
+                    bail!("Unknown destination in `{}`.", instruction.trim());
+                }
                 let result_string = fixed_value + a_comp + comp + dest + jump;
                 Ok(result_string)
             }
             None => bail!("Failed"),
         }
     }
+
+    /// Translates a 16-bit binary word back into its mnemonic form.
+    pub fn decode(&self, word: &str) -> BoxResult<String> {
+        if word.len() != 16 || !word.chars().all(|c| c == '0' || c == '1') {
+            bail!("Invalid word `{}`.", word);
+        }
+        if let Some(address) = word.strip_prefix('0') {
+            return Ok(format!("@{}", u32::from_str_radix(address, 2)?));
+        }
+        if !word.starts_with("111") {
+            bail!("Invalid C-instruction `{}`.", word);
+        }
+
+        let find = |map: &HashMap<&'a str, &'a str>, bits: &str| {
+            map.iter()
+                .find(|(_, value)| **value == bits)
+                .map(|(key, _)| *key)
+        };
+        let comp_map = if &word[3..4] == "1" {
+            &self.m_comp_map
+        } else {
+            &self.a_comp_map
+        };
+        let comp = match find(comp_map, &word[4..10]) {
+            Some(comp) => comp,
+            None => bail!("Unknown computation bits in `{}`.", word),
+        };
+        // every dest and jump bit pattern has a mnemonic
+        let dest = find(&self.dest_map, &word[10..13]).unwrap_or("");
+        let jump = find(&self.jump_map, &word[13..16]).unwrap_or("");
+
+        let mut mnemonic = String::new();
+        if !dest.is_empty() {
+            mnemonic.push_str(dest);
+            mnemonic.push('=');
+        }
+        mnemonic.push_str(comp);
+        if !jump.is_empty() {
+            mnemonic.push(';');
+            mnemonic.push_str(jump);
+        }
+        Ok(mnemonic)
+    }
+
+    pub fn comp_mnemonics(&self) -> Vec<&str> {
+        let mut mnemonics: Vec<&str> = self
+            .a_comp_map
+            .keys()
+            .chain(self.m_comp_map.keys())
+            .cloned()
+            .collect();
+        mnemonics.sort_unstable();
+        mnemonics
+    }
+
+    pub fn dest_mnemonics(&self) -> Vec<&str> {
+        let mut mnemonics: Vec<&str> = self
+            .dest_map
+            .keys()
+            .cloned()
+            .filter(|dest| !dest.is_empty())
+            .collect();
+        mnemonics.sort_unstable();
+        mnemonics
+    }
+
+    pub fn jump_mnemonics(&self) -> Vec<&str> {
+        let mut mnemonics: Vec<&str> = self
+            .jump_map
+            .keys()
+            .cloned()
+            .filter(|jump| !jump.is_empty())
+            .collect();
+        mnemonics.sort_unstable();
+        mnemonics
+    }
 }
 
 #[derive(Debug)]
@@ -266,6 +404,53 @@ impl SymbolTable {
     }
 }
 
+/// A line of assembly source with its trailing comment kept apart from the
+/// instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub number: usize,
+    pub command: String,
+    pub comment: Option<String>,
+}
+
+impl SourceLine {
+    pub fn is_blank(&self) -> bool {
+        self.command.is_empty() && self.comment.is_none()
+    }
+}
+
+/// Splits the input into numbered lines (starting at 1), separating each
+/// instruction from its `//` comment.
+pub fn split_lines(input: &str) -> Vec<SourceLine> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let (command, comment) = match line.find("//") {
+                Some(pos) => (&line[..pos], Some(line[pos + 2..].trim_end().to_string())),
+                None => (line, None),
+            };
+            SourceLine {
+                number: i + 1,
+                command: command.trim().to_string(),
+                comment,
+            }
+        })
+        .collect()
+}
+
+/// Returns the source lines that produce an instruction, so that the line at
+/// index `i` is the one assembled into ROM address `i`.
+pub fn rom_lines(input: &str) -> Vec<SourceLine> {
+    split_lines(input)
+        .into_iter()
+        .filter(|line| {
+            !line.command.is_empty()
+                && !matches!(command_type(&line.command), Ok(Command::LCommand))
+        })
+        .collect()
+}
+
 pub struct Parser<'a> {
     cursor: Cursor<String>,
     length: u64,
@@ -275,10 +460,12 @@ pub struct Parser<'a> {
 
 impl<'a> Parser<'a> {
     pub fn new(input: &str) -> Self {
-        let content = input
-            .lines()
-            .filter(|line| !line.is_empty() && !line.starts_with("//"))
-            .fold(String::new(), |acc, line| acc + line + "\n");
+        let content = split_lines(input)
+            .iter()
+            .filter(|line| !line.command.is_empty())
+            // whitespace inside an instruction is insignificant, e.g. `M = 0`
+            .map(|line| line.command.split_whitespace().collect::<String>())
+            .fold(String::new(), |acc, command| acc + &command + "\n");
         let length = content.len() as u64;
         let code_parser = Code::new();
         let symbol_table = SymbolTable::new();
@@ -336,15 +523,15 @@ impl<'a> Parser<'a> {
             let command = self.read_next().unwrap_or_else(|err| err.to_string());
             let parsed = match command_type(&command) {
                 Ok(command_type @ Command::ACommand) => {
-                    let symbol = SymbolTable::parse_symbol(command, command_type).unwrap();
+                    let symbol = SymbolTable::parse_symbol(command, command_type)?;
                     self.symbol_table.get_address(&symbol)
                 }
                 Ok(Command::CCommand) => self.code_parser.parse(command),
                 Ok(Command::LCommand) => continue,
                 Err(e) => Err(e),
             };
-            bytes.push_str(&parsed.unwrap());
-            bytes.push_str("\n");
+            bytes.push_str(&parsed?);
+            bytes.push('\n');
         }
         Ok(bytes)
     }
@@ -5,6 +5,10 @@ use assembler::Parser;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "verify" {
+        verify(&args[1..]);
+        return;
+    }
 
     let filename = assembler::parse_input(&args).unwrap_or_else(|err| {
         println!("Problem parsing arguments: {}", err);
@@ -32,3 +36,33 @@ fn main() {
         process::exit(1);
     }
 }
+
+/// `assembler verify FILE.asm REFERENCE.hack` compares the assembled program
+/// against a known-good binary and reports the first differing word.
+fn verify(args: &[String]) {
+    if args.len() < 3 {
+        println!("Usage: assembler verify FILE.asm REFERENCE.hack");
+        process::exit(1);
+    }
+    let read = |filename: &str| {
+        assembler::read_file(filename).unwrap_or_else(|err| {
+            println!("Application error: {}", err);
+
+            process::exit(1);
+        })
+    };
+    let source = read(&args[1]);
+    let reference = read(&args[2]);
+
+    match assembler::verify::verify(&source, &reference) {
+        Ok(None) => println!("OK: {} matches {}", args[1], args[2]),
+        Ok(Some(mismatch)) => {
+            println!("{}", mismatch);
+            process::exit(1);
+        }
+        Err(err) => {
+            println!("Couldn't parse to byte code: {}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{rom_lines, BoxResult, Code, Parser, SourceLine};
+use std::fmt;
+
+/// The first ROM word where the assembled program differs from a reference.
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    pub address: usize,
+    /// 1-based character column of the first differing bit, if both words exist
+    pub column: Option<usize>,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+    pub line: Option<SourceLine>,
+}
+
+/// Assembles `source` and compares it word by word against the contents of a
+/// reference `.hack` file. Returns the first mismatch, or `None` if both agree.
+pub fn verify(source: &str, reference: &str) -> BoxResult<Option<Mismatch>> {
+    let mut parser = Parser::new(source);
+    let assembled = parser.to_bytes()?;
+    let actual: Vec<&str> = assembled.lines().collect();
+    let expected: Vec<&str> = reference
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    let length = actual.len().max(expected.len());
+    let mismatch = (0..length)
+        .find(|&i| actual.get(i) != expected.get(i))
+        .map(|address| Mismatch {
+            address,
+            column: first_difference(actual.get(address), expected.get(address)),
+            expected: expected.get(address).map(|word| word.to_string()),
+            actual: actual.get(address).map(|word| word.to_string()),
+            line: rom_lines(source).into_iter().nth(address),
+        });
+    Ok(mismatch)
+}
+
+fn first_difference(actual: Option<&&str>, expected: Option<&&str>) -> Option<usize> {
+    let (actual, expected) = (actual?, expected?);
+    let differing = actual
+        .chars()
+        .zip(expected.chars())
+        .position(|(a, e)| a != e);
+    Some(differing.unwrap_or_else(|| actual.len().min(expected.len())) + 1)
+}
+
+fn describe(code: &Code, word: &Option<String>) -> String {
+    match word {
+        Some(word) => {
+            let mnemonic = code.decode(word).unwrap_or_else(|_| String::from("???"));
+            format!("{}  {}", word, mnemonic)
+        }
+        None => String::from("<missing>"),
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let code = Code::new();
+        match self.column {
+            Some(column) => writeln!(
+                fmt,
+                "Mismatch at ROM address {}, column {}",
+                self.address, column
+            )?,
+            None => writeln!(fmt, "Mismatch at ROM address {}", self.address)?,
+        }
+        writeln!(fmt, "  expected: {}", describe(&code, &self.expected))?;
+        writeln!(fmt, "  actual:   {}", describe(&code, &self.actual))?;
+        match &self.line {
+            Some(line) => write!(fmt, "  source line {}: {}", line.number, line.command),
+            None => write!(fmt, "  source line: <none>"),
+        }
+    }
+}
@@ -3,6 +3,11 @@ use assembler::Command;
 use assembler::Parser;
 use assembler::SymbolTable;
 use assembler::command_type;
+use assembler::format::format;
+use assembler::rom_lines;
+use assembler::verify::verify;
+use assembler::split_lines;
+use assembler::SourceLine;
 use std::collections::HashMap;
 
 const INPUT_WITHOUT_SYMBOLS: &str = r#"// This file is part of www.nand2tetris.org
@@ -157,6 +162,29 @@ fn test_invalid_code_nonexistent_jmp() {
     code_parser.parse(invalid_command).unwrap();
 }
 
+#[test]
+#[should_panic]
+fn test_invalid_code_unknown_comp() {
+    let invalid_command = "D=A+M".to_string();
+    let code_parser = Code::new();
+    code_parser.parse(invalid_command).unwrap();
+}
+
+#[test]
+fn test_to_bytes_reports_invalid_commands() {
+    assert!(Parser::new("@2\nD=A+M\n").to_bytes().is_err());
+    assert!(Parser::new("@2\nX=A\n").to_bytes().is_err());
+    assert!(Parser::new("@-1\n").to_bytes().is_err());
+}
+
+#[test]
+fn test_mnemonics() {
+    let code_parser = Code::new();
+    assert_eq!(28, code_parser.comp_mnemonics().len());
+    assert_eq!(7, code_parser.dest_mnemonics().len());
+    assert_eq!(vec!["JEQ", "JGE", "JGT", "JLE", "JLT", "JMP", "JNE"], code_parser.jump_mnemonics());
+}
+
 #[test]
 #[should_panic]
 fn test_invalid_code_dest_and_jmp() {
@@ -351,3 +379,198 @@ fn test_assembler_with_symbols_rect() {
     let mut parser = Parser::new(INPUT_WITH_SYMBOLS2);
     assert_eq!(assembler_output, parser.to_bytes().unwrap());
 }
+
+#[test]
+fn test_assembler_symbol_characters() {
+    // symbols may hold letters, digits, '_', '.', '$' and ':' anywhere but
+    // at the start, where digits aren't allowed
+    let input = r#"(Main.f$LOOP.1)
+@Main.f$LOOP.1
+0;JMP
+(Main.f$A:b)
+@Main.f$A:b
+0;JMP
+@Foo.bar$ret.0
+@Foo.bar$ret.1
+@$:_.x
+(Foo.bar$ret.0)
+"#;
+    let assembler_output = r#"0000000000000000
+1110101010000111
+0000000000000010
+1110101010000111
+0000000000000111
+0000000000010000
+0000000000010001
+"#;
+    let mut parser = Parser::new(input);
+    assert_eq!(assembler_output, parser.to_bytes().unwrap());
+}
+
+#[test]
+fn test_split_lines_keeps_comments() {
+    let lines = split_lines("// header\n   D=M   // D = first\n\n(LOOP)");
+    assert_eq!(
+        SourceLine {
+            number: 2,
+            command: String::from("D=M"),
+            comment: Some(String::from(" D = first")),
+        },
+        lines[1]
+    );
+    assert_eq!("", lines[0].command);
+    assert!(lines[2].is_blank());
+    assert_eq!("(LOOP)", lines[3].command);
+}
+
+#[test]
+fn test_init_parser_indented_comment() {
+    let parser = Parser::new("   @2\n   // set D\n   D=A   // D = 2\n");
+    assert_eq!("@2\nD=A\n", parser.to_string());
+}
+
+#[test]
+fn test_init_parser_instruction_whitespace() {
+    let mut parser = Parser::new("\t@2\n\tD = M + 1 // D = M+1\n  AM = M - 1\n\t0 ; JMP\n");
+    assert_eq!("@2\nD=M+1\nAM=M-1\n0;JMP\n", parser.to_string());
+    let mut plain = Parser::new("@2\nD=M+1\nAM=M-1\n0;JMP\n");
+    assert_eq!(plain.to_bytes().unwrap(), parser.to_bytes().unwrap());
+}
+
+#[test]
+fn test_normalize_codes() {
+    let code_parser = Code::new();
+    assert_eq!("MD=D+A", code_parser.normalize("DM = A + D").unwrap());
+    assert_eq!("AMD=D|M", code_parser.normalize("DMA=M|D").unwrap());
+    assert_eq!("D;JGT", code_parser.normalize("D ; JGT").unwrap());
+    assert_eq!("M=M-1", code_parser.normalize("M=M-1").unwrap());
+}
+
+#[test]
+#[should_panic]
+fn test_normalize_invalid_non_commutative() {
+    let code_parser = Code::new();
+    code_parser.normalize("D=1-D").unwrap();
+}
+
+#[test]
+fn test_format() {
+    let input = r#"// Computes R2 = max(R0, R1)
+
+
+      @R0
+ D = M // D = first number
+    // jump if positive
+@OUTPUT_FIRST
+  D;JGT
+( OUTPUT_FIRST )   // first is greater
+@ball.setdestination$if_true0 // long symbol
+
+"#;
+    let expected = r#"// Computes R2 = max(R0, R1)
+
+   @R0
+   D=M              // D = first number
+   // jump if positive
+   @OUTPUT_FIRST
+   D;JGT
+(OUTPUT_FIRST)      // first is greater
+   @ball.setdestination$if_true0  // long symbol
+"#;
+    let formatted = format(input).unwrap();
+    assert_eq!(expected, formatted);
+    assert_eq!(formatted, format(&formatted).unwrap());
+}
+
+#[test]
+fn test_format_reports_line() {
+    let err = format("@2\nD=Z\n").unwrap_err();
+    assert!(err.to_string().starts_with("line 2:"));
+}
+
+#[test]
+fn test_decode() {
+    let code_parser = Code::new();
+    assert_eq!("@2", code_parser.decode("0000000000000010").unwrap());
+    assert_eq!("D=D+A", code_parser.decode("1110000010010000").unwrap());
+    assert_eq!("D;JGT", code_parser.decode("1110001100000001").unwrap());
+    assert_eq!("AM=M-1", code_parser.decode("1111110010101000").unwrap());
+    assert!(code_parser.decode("1010001100000001").is_err());
+    assert!(code_parser.decode("111").is_err());
+}
+
+#[test]
+fn test_rom_lines() {
+    let lines = rom_lines(INPUT_WITH_SYMBOLS);
+    assert_eq!(16, lines.len());
+    assert_eq!(19, lines[10].number);
+    assert_eq!("@R0", lines[10].command);
+}
+
+#[test]
+fn test_verify() {
+    let mut parser = Parser::new(INPUT_WITH_SYMBOLS);
+    let reference = parser.to_bytes().unwrap();
+    assert_eq!(None, verify(INPUT_WITH_SYMBOLS, &reference).unwrap());
+
+    let broken = reference.replacen("1110001100000001", "1110001100000010", 1);
+    let mismatch = verify(INPUT_WITH_SYMBOLS, &broken).unwrap().unwrap();
+    assert_eq!(5, mismatch.address);
+    assert_eq!(Some(15), mismatch.column);
+    assert_eq!(Some(String::from("1110001100000001")), mismatch.actual);
+    assert_eq!(13, mismatch.line.unwrap().number);
+
+    let truncated: String = reference
+        .lines()
+        .take(3)
+        .map(|l| format!("{}\n", l))
+        .collect();
+    let mismatch = verify(INPUT_WITH_SYMBOLS, &truncated).unwrap().unwrap();
+    assert_eq!(3, mismatch.address);
+    assert_eq!(None, mismatch.column);
+    assert_eq!(None, mismatch.expected);
+}
+
+#[test]
+fn test_verify_reference_files() {
+    let programs = [
+        (
+            include_str!("../../projects/06/add/Add.asm"),
+            include_str!("../../projects/06/add/Add.hack"),
+        ),
+        (
+            include_str!("../../projects/06/max/Max.asm"),
+            include_str!("../../projects/06/max/Max.hack"),
+        ),
+        (
+            include_str!("../../projects/06/rect/Rect.asm"),
+            include_str!("../../projects/06/rect/Rect.hack"),
+        ),
+        (
+            include_str!("../../projects/06/pong/Pong.asm"),
+            include_str!("../../projects/06/pong/Pong.hack"),
+        ),
+    ];
+    for (source, reference) in programs.iter() {
+        assert_eq!(None, verify(source, reference).unwrap());
+    }
+}
+
+#[test]
+fn test_verify_flipped_bit() {
+    let source = include_str!("../../projects/06/max/Max.asm");
+    let mut words: Vec<String> = include_str!("../../projects/06/max/Max.hack")
+        .lines()
+        .map(String::from)
+        .collect();
+    // clearing the a-bit turns D=D-M at ROM 3 into D=D-A
+    assert_eq!("1111010011010000", words[3]);
+    words[3].replace_range(3..4, "0");
+    let mismatch = verify(source, &words.join("\n")).unwrap().unwrap();
+    assert_eq!(3, mismatch.address);
+    assert_eq!(Some(4), mismatch.column);
+    assert_eq!(Some(String::from("1110010011010000")), mismatch.expected);
+    let line = mismatch.line.unwrap();
+    assert_eq!(11, line.number);
+    assert_eq!("D=D-M", line.command);
+}
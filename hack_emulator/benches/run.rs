@@ -0,0 +1,41 @@
+use assembler::Parser;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use hack_emulator::Computer;
+
+const PONG: &str = include_str!("../../projects/06/pong/Pong.asm");
+const CYCLES: u64 = 10_000_000;
+
+fn pong() -> Computer {
+    let mut parser = Parser::new(PONG);
+    Computer::from_hack(&parser.to_bytes().unwrap()).unwrap()
+}
+
+/// Pong runs its game loop without halting, so every iteration executes
+/// exactly `CYCLES` instructions. Throughput is reported in instructions.
+fn bench_run(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pong");
+    group.throughput(Throughput::Elements(CYCLES));
+    group.sample_size(20);
+    group.bench_function("run", |b| {
+        b.iter_batched(
+            pong,
+            |mut computer| assert_eq!(CYCLES, computer.run(CYCLES)),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("step", |b| {
+        b.iter_batched(
+            pong,
+            |mut computer| {
+                for _ in 0..CYCLES {
+                    computer.step();
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_run);
+criterion_main!(benches);
@@ -0,0 +1,34 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use hack_emulator::cli::{self, fail};
+use hack_emulator::compare::compare;
+
+const USAGE: &str = "Usage: hack_compare OUTPUT.out COMPARE.cmp";
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        cli::usage_error("expected two files", USAGE);
+    }
+    let read = |filename: &String| {
+        fs::read_to_string(filename)
+            .unwrap_or_else(|err| fail(&format!("Failed reading {}", filename), err))
+    };
+    let mismatches = compare(&read(&args[1]), &read(&args[2]));
+    if mismatches.is_empty() {
+        println!("Comparison ended successfully");
+        return;
+    }
+    for mismatch in &mismatches {
+        println!("{}: {}", args[1], mismatch);
+    }
+    let plural = if mismatches.len() == 1 { "" } else { "es" };
+    println!(
+        "Comparison failed with {} mismatch{}",
+        mismatches.len(),
+        plural
+    );
+    process::exit(1);
+}
@@ -0,0 +1,293 @@
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crossterm::cursor::MoveTo;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::queue;
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType};
+
+use hack_emulator::cli::{self, fail};
+use hack_emulator::debugger::{Debugger, Stop};
+use hack_emulator::tui::Terminal;
+
+/// Instructions a single continue, step-over or run-to may execute before
+/// control returns to the user.
+const MAX_CYCLES: u64 = 50_000_000;
+const SIDE_WIDTH: u16 = 30;
+const HELP: &str = "s/S step fwd/back  n next  c/C continue fwd/back  r run to  b break  \
+w watch  W watchpoint  v save  L load  R restart  q quit";
+
+enum Prompt {
+    Break,
+    RunTo,
+    Watch,
+    Watchpoint,
+    Save,
+    Load,
+}
+
+impl Prompt {
+    fn title(&self) -> &str {
+        match self {
+            Prompt::Break => "toggle breakpoint at",
+            Prompt::RunTo => "run to label",
+            Prompt::Watch => "watch RAM",
+            Prompt::Watchpoint => "toggle watchpoint on RAM",
+            Prompt::Save => "save snapshot to",
+            Prompt::Load => "load snapshot from",
+        }
+    }
+}
+
+struct App {
+    debugger: Debugger,
+    source: Vec<String>,
+    message: String,
+    prompt: Option<(Prompt, String)>,
+}
+
+impl App {
+    fn location_name(&self, address: u16) -> String {
+        match self
+            .debugger
+            .program()
+            .labels()
+            .iter()
+            .find(|(_, label_address)| *label_address == address)
+        {
+            Some((label, _)) => label.clone(),
+            None => format!("ROM[{}]", address),
+        }
+    }
+
+    fn report(&mut self, stop: Stop) {
+        self.message = match stop {
+            Stop::Done => String::new(),
+            Stop::Breakpoint(address) => format!("Breakpoint at {}", self.location_name(address)),
+            Stop::Watchpoint(address) => format!("Watchpoint on RAM[{}]", address),
+            Stop::Halted => String::from("Program halted"),
+            Stop::Limit => format!("Paused after {} cycles", MAX_CYCLES),
+            Stop::HistoryStart => String::from("Reached the start of the recorded history"),
+        };
+    }
+
+    fn submit(&mut self, prompt: Prompt, input: &str) {
+        let input = input.trim();
+        let result = match prompt {
+            Prompt::Break => self.debugger.toggle_breakpoint(input).map(|set| {
+                let state = if set { "set" } else { "removed" };
+                format!("Breakpoint at {} {}", input, state)
+            }),
+            Prompt::RunTo => self.debugger.run_to(input, MAX_CYCLES).map(|stop| {
+                self.report(stop);
+                self.message.clone()
+            }),
+            Prompt::Watch => self
+                .debugger
+                .add_watch(input)
+                .map(|address| format!("Watching {} at RAM[{}]", input, address)),
+            Prompt::Watchpoint => self.debugger.toggle_watchpoint(input).map(|set| {
+                let state = if set { "set" } else { "removed" };
+                format!("Watchpoint on {} {}", input, state)
+            }),
+            Prompt::Save => self
+                .debugger
+                .save_snapshot(Path::new(input))
+                .map(|_| format!("Saved snapshot to {}", input)),
+            Prompt::Load => self
+                .debugger
+                .load_snapshot(Path::new(input))
+                .map(|_| format!("Loaded snapshot from {}", input)),
+        };
+        self.message = result.unwrap_or_else(|err| err.to_string());
+    }
+
+    /// Handles a key press and returns false when the user quits.
+    fn handle(&mut self, code: KeyCode) -> bool {
+        if let Some((prompt, mut input)) = self.prompt.take() {
+            match code {
+                KeyCode::Enter => self.submit(prompt, &input),
+                KeyCode::Esc => {}
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.prompt = Some((prompt, input));
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    self.prompt = Some((prompt, input));
+                }
+                _ => self.prompt = Some((prompt, input)),
+            }
+            return true;
+        }
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('s') => {
+                let stop = self.debugger.step();
+                self.report(stop);
+            }
+            KeyCode::Char('n') => {
+                let stop = self.debugger.step_over(MAX_CYCLES);
+                self.report(stop);
+            }
+            KeyCode::Char('c') => {
+                let stop = self.debugger.resume(MAX_CYCLES);
+                self.report(stop);
+            }
+            KeyCode::Char('S') => {
+                let stop = self.debugger.reverse_step();
+                self.report(stop);
+            }
+            KeyCode::Char('C') => {
+                let stop = self.debugger.reverse_resume();
+                self.report(stop);
+            }
+            KeyCode::Char('R') => {
+                self.debugger.restart();
+                self.message = String::from("Restarted");
+            }
+            KeyCode::Char('b') => self.prompt = Some((Prompt::Break, String::new())),
+            KeyCode::Char('r') => self.prompt = Some((Prompt::RunTo, String::new())),
+            KeyCode::Char('w') => self.prompt = Some((Prompt::Watch, String::new())),
+            KeyCode::Char('W') => self.prompt = Some((Prompt::Watchpoint, String::new())),
+            KeyCode::Char('v') => self.prompt = Some((Prompt::Save, String::new())),
+            KeyCode::Char('L') => self.prompt = Some((Prompt::Load, String::new())),
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let source_width = width.saturating_sub(SIDE_WIDTH + 1) as usize;
+        let source_height = height.saturating_sub(2) as usize;
+        let computer = self.debugger.computer();
+        let program = self.debugger.program();
+
+        let current = program.source_line(computer.pc()).map(|line| line.number);
+        let breakpoint_lines: Vec<usize> = self
+            .debugger
+            .breakpoints()
+            .iter()
+            .filter_map(|address| program.source_line(*address))
+            .map(|line| line.number)
+            .collect();
+
+        queue!(out, Clear(ClearType::All))?;
+
+        // source pane, scrolled to keep the current line in the middle
+        let first = current
+            .unwrap_or(1)
+            .saturating_sub(source_height / 2 + 1)
+            .min(self.source.len().saturating_sub(source_height));
+        for (row, (i, text)) in self
+            .source
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(source_height)
+            .enumerate()
+        {
+            let number = i + 1;
+            let marker = if Some(number) == current { '>' } else { ' ' };
+            let breakpoint = if breakpoint_lines.contains(&number) {
+                '*'
+            } else {
+                ' '
+            };
+            let line: String = format!("{}{} {:>4}  {}", marker, breakpoint, number, text)
+                .chars()
+                .take(source_width)
+                .collect();
+            queue!(out, MoveTo(0, row as u16))?;
+            if Some(number) == current {
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reverse),
+                    Print(format!("{:width$}", line, width = source_width)),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            } else {
+                queue!(out, Print(line))?;
+            }
+        }
+
+        // registers and watched RAM
+        let label = program.enclosing_label(computer.pc()).unwrap_or("-");
+        let mut side = vec![
+            format!("PC      {}", computer.pc()),
+            format!("A       {}", computer.a() as i16),
+            format!("D       {}", computer.d() as i16),
+            format!("M       {}", computer.read(computer.a()) as i16),
+            format!("cycles  {}", computer.cycles()),
+            format!("history {}", self.debugger.recorder().history()),
+            format!("in      {}", label),
+            String::new(),
+            String::from("RAM watch"),
+        ];
+        for (name, address) in self.debugger.watches() {
+            side.push(format!(
+                "{:<10} [{}] {}",
+                name,
+                address,
+                computer.read(*address) as i16
+            ));
+        }
+        let column = source_width as u16 + 1;
+        for (row, text) in side.iter().take(source_height).enumerate() {
+            let text: String = text.chars().take(SIDE_WIDTH as usize).collect();
+            queue!(out, MoveTo(column, row as u16), Print(text))?;
+        }
+
+        let status = match &self.prompt {
+            Some((prompt, input)) => format!("{}: {}", prompt.title(), input),
+            None => String::from(HELP),
+        };
+        queue!(
+            out,
+            MoveTo(0, height.saturating_sub(2)),
+            Print(&self.message),
+            MoveTo(0, height.saturating_sub(1)),
+            Print(status)
+        )?;
+        out.flush()
+    }
+}
+
+fn run(app: &mut App) -> io::Result<()> {
+    let mut out = io::stdout();
+    loop {
+        app.draw(&mut out)?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !app.handle(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let filename = assembler::parse_input(&args)
+        .unwrap_or_else(|err| cli::usage_error(err, "Usage: hack_debug FILE.asm"));
+    let content =
+        assembler::read_file(filename).unwrap_or_else(|err| fail("Application error", err));
+    let debugger = Debugger::new(&content)
+        .unwrap_or_else(|err| fail(&format!("Couldn't assemble {}", filename), err));
+    let mut app = App {
+        debugger,
+        source: content
+            .lines()
+            .map(|line| line.replace('\t', "    "))
+            .collect(),
+        message: String::new(),
+        prompt: None,
+    };
+
+    let result = Terminal::enter(false).and_then(|_terminal| run(&mut app));
+    if let Err(err) = result {
+        fail("Terminal error", err);
+    }
+}
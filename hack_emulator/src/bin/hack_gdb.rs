@@ -0,0 +1,93 @@
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+
+use hack_emulator::cli::{self, fail};
+use hack_emulator::gdb::{self, GdbStub};
+use hack_emulator::program::Program;
+use hack_emulator::Computer;
+
+const USAGE: &str = "Usage: hack_gdb PROGRAM.asm|PROGRAM.hack [--symbols SOURCE.asm] [--port N]";
+
+struct Options {
+    program: String,
+    symbols: Option<String>,
+    port: u16,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        program: String::new(),
+        symbols: None,
+        port: 1234,
+    };
+    let mut args = cli::Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg {
+            "--symbols" => options.symbols = Some(args.value(arg)?),
+            "--port" => options.port = args.parse(arg)?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => options.program = arg.to_string(),
+        }
+    }
+    if options.program.is_empty() {
+        return Err(String::from("not enough arguments"));
+    }
+    Ok(options)
+}
+
+fn assemble(filename: &str) -> Program {
+    let source = fs::read_to_string(filename)
+        .unwrap_or_else(|err| fail(&format!("Failed reading {}", filename), err));
+    Program::assemble(&source)
+        .unwrap_or_else(|err| fail(&format!("Couldn't assemble {}", filename), err))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_options(&args).unwrap_or_else(|err| cli::usage_error(err, USAGE));
+
+    // a .hack program without --symbols runs without labels
+    let program = if options.program.ends_with(".hack") {
+        options.symbols.as_deref().map(assemble)
+    } else {
+        Some(assemble(&options.program))
+    };
+    let words = if options.program.ends_with(".hack") {
+        hack_emulator::read_hack(&options.program)
+            .unwrap_or_else(|err| fail("Application error", err))
+    } else {
+        program.as_ref().unwrap().words().to_vec()
+    };
+    if let Some(program) = &program {
+        if words != program.words() {
+            fail(
+                "Symbols don't match the program",
+                format!(
+                    "{} doesn't assemble to {}",
+                    options.symbols.as_deref().unwrap_or_default(),
+                    options.program
+                ),
+            );
+        }
+    }
+    let labels = program
+        .map(|program| program.labels().to_vec())
+        .unwrap_or_default();
+    let mut stub = GdbStub::new(Computer::from_program(&words), labels);
+
+    let address = format!("127.0.0.1:{}", options.port);
+    let listener = TcpListener::bind(&address).unwrap_or_else(|err| fail("Failed listening", err));
+    println!(
+        "Listening on {}, connect with `target remote {}`",
+        address, address
+    );
+    let (stream, peer) = listener
+        .accept()
+        .unwrap_or_else(|err| fail("Failed accepting", err));
+    println!("Debugger connected from {}", peer);
+    if let Err(err) = gdb::serve(&mut stub, stream) {
+        fail("Connection error", err);
+    }
+    println!("Debugger detached");
+}
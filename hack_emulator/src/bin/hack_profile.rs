@@ -0,0 +1,91 @@
+use std::env;
+use std::fs;
+
+use hack_emulator::cli::{self, fail};
+use hack_emulator::keyboard::KeyScript;
+use hack_emulator::profiler::Profiler;
+use hack_emulator::program::Program;
+use hack_emulator::Computer;
+
+const USAGE: &str = "Usage: hack_profile PROGRAM.asm|PROGRAM.hack [--symbols SOURCE.asm] \
+[--cycles N] [--keys SCRIPT] [--collapsed FILE]";
+
+struct Options {
+    program: String,
+    symbols: Option<String>,
+    cycles: u64,
+    keys: Option<String>,
+    collapsed: Option<String>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        program: String::new(),
+        symbols: None,
+        cycles: 10_000_000,
+        keys: None,
+        collapsed: None,
+    };
+    let mut args = cli::Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg {
+            "--symbols" => options.symbols = Some(args.value(arg)?),
+            "--cycles" => options.cycles = args.parse(arg)?,
+            "--keys" => options.keys = Some(args.value(arg)?),
+            "--collapsed" => options.collapsed = Some(args.value(arg)?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => options.program = arg.to_string(),
+        }
+    }
+    if options.program.is_empty() {
+        return Err(String::from("not enough arguments"));
+    }
+    if options.program.ends_with(".hack") && options.symbols.is_none() {
+        return Err(String::from("a .hack program needs --symbols SOURCE.asm"));
+    }
+    Ok(options)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_options(&args).unwrap_or_else(|err| cli::usage_error(err, USAGE));
+
+    let source_file = options.symbols.as_ref().unwrap_or(&options.program);
+    let source = fs::read_to_string(source_file)
+        .unwrap_or_else(|err| fail(&format!("Failed reading {}", source_file), err));
+    let program = Program::assemble(&source)
+        .unwrap_or_else(|err| fail(&format!("Couldn't assemble {}", source_file), err));
+    let words = if options.symbols.is_some() {
+        hack_emulator::read_hack(&options.program)
+            .unwrap_or_else(|err| fail("Application error", err))
+    } else {
+        program.words().to_vec()
+    };
+    if words != program.words() {
+        fail(
+            "Symbols don't match the program",
+            format!("{} doesn't assemble to {}", source_file, options.program),
+        );
+    }
+
+    let script = match &options.keys {
+        Some(filename) => fs::read_to_string(filename)
+            .map_err(|err| err.into())
+            .and_then(|content| KeyScript::parse(&content))
+            .unwrap_or_else(|err| fail("Failed reading key script", err)),
+        None => KeyScript::default(),
+    };
+
+    let mut computer = Computer::from_program(&words);
+    let mut profiler = Profiler::new(&program);
+    script.run_with(&mut computer, options.cycles, |computer, cycles| {
+        profiler.run(computer, cycles)
+    });
+    print!("{}", profiler.flat_report());
+
+    if let Some(path) = &options.collapsed {
+        if let Err(err) = fs::write(path, profiler.collapsed()) {
+            fail("Failed writing file", err);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use hack_emulator::cli::{self, fail};
+use hack_emulator::keyboard::KeyScript;
+use hack_emulator::screen::Frame;
+use hack_emulator::Computer;
+
+const USAGE: &str = "Usage: hack_run PROGRAM.hack [--cycles N] [--every N --out-dir DIR] \
+[--format png|pbm] [--screenshot FILE] [--golden FILE] [--keys SCRIPT]";
+
+struct Options {
+    program: String,
+    cycles: u64,
+    every: Option<u64>,
+    out_dir: PathBuf,
+    format: String,
+    screenshot: Option<PathBuf>,
+    golden: Option<PathBuf>,
+    keys: Option<String>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        program: String::new(),
+        cycles: 1_000_000,
+        every: None,
+        out_dir: PathBuf::from("."),
+        format: String::from("png"),
+        screenshot: None,
+        golden: None,
+        keys: None,
+    };
+    let mut args = cli::Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg {
+            "--cycles" => options.cycles = args.parse(arg)?,
+            "--every" => options.every = Some(args.parse(arg)?),
+            "--out-dir" => options.out_dir = PathBuf::from(args.value(arg)?),
+            "--format" => options.format = args.value(arg)?,
+            "--screenshot" => options.screenshot = Some(PathBuf::from(args.value(arg)?)),
+            "--golden" => options.golden = Some(PathBuf::from(args.value(arg)?)),
+            "--keys" => options.keys = Some(args.value(arg)?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => options.program = arg.to_string(),
+        }
+    }
+    if options.program.is_empty() {
+        return Err(String::from("not enough arguments"));
+    }
+    if options.format != "png" && options.format != "pbm" {
+        return Err(format!("unsupported format {}", options.format));
+    }
+    if options.every == Some(0) {
+        return Err(String::from("--every needs a positive number of cycles"));
+    }
+    Ok(options)
+}
+
+fn save(frame: &Frame, path: &Path) {
+    if let Err(err) = frame.save(path) {
+        fail("Failed writing image", err);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_options(&args).unwrap_or_else(|err| cli::usage_error(err, USAGE));
+
+    let program = hack_emulator::read_hack(&options.program)
+        .unwrap_or_else(|err| fail("Application error", err));
+    let mut computer = Computer::from_program(&program);
+    let script = match &options.keys {
+        Some(filename) => fs::read_to_string(filename)
+            .map_err(|err| err.into())
+            .and_then(|content| KeyScript::parse(&content))
+            .unwrap_or_else(|err| fail("Failed reading key script", err)),
+        None => KeyScript::default(),
+    };
+
+    // without --every the whole run is a single chunk
+    let chunk = options.every.unwrap_or(options.cycles);
+    while computer.cycles() < options.cycles && !computer.is_halted() {
+        let remaining = options.cycles - computer.cycles();
+        script.run(&mut computer, chunk.min(remaining));
+        if options.every.is_some() {
+            let filename = format!("frame_{:010}.{}", computer.cycles(), options.format);
+            save(
+                &Frame::from_screen(computer.screen()),
+                &options.out_dir.join(filename),
+            );
+        }
+    }
+    let state = if computer.is_halted() {
+        "halted"
+    } else {
+        "stopped"
+    };
+    println!("{} after {} cycles", state, computer.cycles());
+
+    let frame = Frame::from_screen(computer.screen());
+    if let Some(path) = &options.screenshot {
+        save(&frame, path);
+    }
+    if let Some(path) = &options.golden {
+        let golden =
+            Frame::load(path).unwrap_or_else(|err| fail("Failed reading golden image", err));
+        match frame.diff(&golden) {
+            Ok(diff) if diff.is_empty() => println!("Screen matches {}", path.display()),
+            Ok(diff) => {
+                let (x, y) = diff.first.unwrap_or((0, 0));
+                println!(
+                    "Screen differs from {} in {} pixels, first at x={} y={}",
+                    path.display(),
+                    diff.pixels,
+                    x,
+                    y
+                );
+                process::exit(1);
+            }
+            Err(err) => fail("Failed comparing with golden image", err),
+        }
+    }
+}
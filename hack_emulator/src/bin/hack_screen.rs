@@ -0,0 +1,231 @@
+use std::env;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::MoveTo;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::queue;
+use crossterm::style::Print;
+use crossterm::terminal::{self, Clear, ClearType};
+
+use hack_emulator::cli;
+use hack_emulator::keyboard;
+use hack_emulator::screen::{Frame, SCREEN_HEIGHT, SCREEN_WIDTH};
+use hack_emulator::tui::Terminal;
+use hack_emulator::{Computer, KBD};
+
+const USAGE: &str = "Usage: hack_screen PROGRAM.hack [--glyphs braille|half] [--scale N] \
+[--fps N] [--speed N]";
+/// Most terminals only report key presses, so a key counts as held until
+/// no repeat arrived for this long.
+const KEY_HOLD: Duration = Duration::from_millis(150);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Glyphs {
+    Braille,
+    HalfBlock,
+}
+
+impl Glyphs {
+    /// Pixels per character horizontally and vertically.
+    fn cell(self) -> (usize, usize) {
+        match self {
+            Glyphs::Braille => (2, 4),
+            Glyphs::HalfBlock => (1, 2),
+        }
+    }
+
+    fn render(self, frame: &Frame) -> Vec<String> {
+        match self {
+            Glyphs::Braille => frame.to_braille(),
+            Glyphs::HalfBlock => frame.to_half_blocks(),
+        }
+    }
+}
+
+struct Options {
+    program: String,
+    glyphs: Glyphs,
+    scale: Option<usize>,
+    fps: u64,
+    speed: u64,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        program: String::new(),
+        glyphs: Glyphs::Braille,
+        scale: None,
+        fps: 30,
+        speed: 10_000_000,
+    };
+    let mut args = cli::Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg {
+            "--glyphs" => {
+                options.glyphs = match args.value(arg)?.as_str() {
+                    "braille" => Glyphs::Braille,
+                    "half" => Glyphs::HalfBlock,
+                    other => return Err(format!("unsupported glyphs {}", other)),
+                }
+            }
+            "--scale" => options.scale = Some(args.parse(arg)?),
+            "--fps" => options.fps = args.parse(arg)?,
+            "--speed" => options.speed = args.parse(arg)?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => options.program = arg.to_string(),
+        }
+    }
+    if options.program.is_empty() {
+        return Err(String::from("not enough arguments"));
+    }
+    if options.scale == Some(0) || options.fps == 0 {
+        return Err(String::from("--scale and --fps need positive numbers"));
+    }
+    Ok(options)
+}
+
+/// The Hack key code of a terminal key, None for keys the Hack keyboard
+/// doesn't have.
+fn hack_key(code: KeyCode) -> Option<u16> {
+    let key = match code {
+        KeyCode::Char(c) if c.is_ascii() && !c.is_ascii_control() => c as u16,
+        KeyCode::Enter => keyboard::NEWLINE,
+        KeyCode::Backspace => keyboard::BACKSPACE,
+        KeyCode::Left => keyboard::LEFT,
+        KeyCode::Up => keyboard::UP,
+        KeyCode::Right => keyboard::RIGHT,
+        KeyCode::Down => keyboard::DOWN,
+        KeyCode::Home => keyboard::HOME,
+        KeyCode::End => keyboard::END,
+        KeyCode::PageUp => keyboard::PAGE_UP,
+        KeyCode::PageDown => keyboard::PAGE_DOWN,
+        KeyCode::Insert => keyboard::INSERT,
+        KeyCode::Delete => keyboard::DELETE,
+        KeyCode::Esc => keyboard::ESC,
+        KeyCode::F(n) if (1..=12).contains(&n) => keyboard::F1 + n as u16 - 1,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// The smallest scale at which the screen fits the terminal, leaving a line
+/// for the status.
+fn fitting_scale(glyphs: Glyphs) -> io::Result<usize> {
+    let (columns, rows) = terminal::size()?;
+    let (cell_width, cell_height) = glyphs.cell();
+    let columns = (columns as usize).max(1) * cell_width;
+    let rows = (rows as usize).saturating_sub(1).max(1) * cell_height;
+    Ok((1..)
+        .find(|scale| {
+            SCREEN_WIDTH.div_ceil(*scale) <= columns && SCREEN_HEIGHT.div_ceil(*scale) <= rows
+        })
+        .unwrap())
+}
+
+/// Runs the program at the configured speed, drawing a frame at a time.
+/// Without `reports_release` a key counts as released after `KEY_HOLD`.
+fn run(computer: &mut Computer, options: &Options, reports_release: bool) -> io::Result<()> {
+    let mut out = io::stdout();
+    let frame_time = Duration::from_nanos(1_000_000_000 / options.fps);
+    let cycles_per_frame = options.speed / options.fps;
+    let mut scale = match options.scale {
+        Some(scale) => scale,
+        None => fitting_scale(options.glyphs)?,
+    };
+    let mut lines: Vec<String> = Vec::new();
+    let mut released_at: Option<Instant> = None;
+    loop {
+        let start = Instant::now();
+        computer.run(cycles_per_frame);
+
+        let frame = Frame::from_screen(computer.screen()).scaled(scale);
+        let rendered = options.glyphs.render(&frame);
+        // only redraw the lines that changed, to spare slow connections
+        for (row, line) in rendered.iter().enumerate() {
+            if lines.get(row) != Some(line) {
+                queue!(out, MoveTo(0, row as u16), Print(line))?;
+            }
+        }
+        let status = format!(
+            "{} | scale {} | {} fps | key {:>3} | Ctrl-C quits",
+            options.program,
+            scale,
+            options.fps,
+            computer.read(KBD as u16)
+        );
+        queue!(
+            out,
+            MoveTo(0, rendered.len() as u16),
+            Clear(ClearType::CurrentLine),
+            Print(status)
+        )?;
+        out.flush()?;
+        lines = rendered;
+
+        // forward keys until the next frame is due, at least once per frame
+        loop {
+            if let Some(at) = released_at {
+                if Instant::now() >= at {
+                    computer.write(KBD as u16, 0);
+                    released_at = None;
+                }
+            }
+            let remaining = frame_time.saturating_sub(start.elapsed());
+            if !event::poll(remaining.min(KEY_HOLD))? {
+                if remaining.is_zero() {
+                    break;
+                }
+                continue;
+            }
+            match event::read()? {
+                Event::Key(key)
+                    if key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL) =>
+                {
+                    return Ok(());
+                }
+                Event::Key(key) => match (key.kind, hack_key(key.code)) {
+                    (KeyEventKind::Release, _) => {
+                        computer.write(KBD as u16, 0);
+                        released_at = None;
+                    }
+                    (_, Some(code)) => {
+                        computer.write(KBD as u16, code);
+                        if !reports_release {
+                            released_at = Some(Instant::now() + KEY_HOLD);
+                        }
+                    }
+                    _ => {}
+                },
+                Event::Resize(..) => {
+                    if options.scale.is_none() {
+                        scale = fitting_scale(options.glyphs)?;
+                    }
+                    lines.clear();
+                    queue!(out, Clear(ClearType::All))?;
+                }
+                _ => {}
+            }
+            if start.elapsed() >= frame_time {
+                break;
+            }
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_options(&args).unwrap_or_else(|err| cli::usage_error(err, USAGE));
+    let program = hack_emulator::read_hack(&options.program)
+        .unwrap_or_else(|err| cli::fail("Application error", err));
+    let mut computer = Computer::from_program(&program);
+
+    // report key releases where the terminal supports it
+    let reports_release = terminal::supports_keyboard_enhancement().unwrap_or(false);
+    let result = Terminal::enter(reports_release)
+        .and_then(|_terminal| run(&mut computer, &options, reports_release));
+    if let Err(err) = result {
+        cli::fail("Terminal error", err);
+    }
+}
@@ -0,0 +1,44 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use hack_emulator::cli::{self, fail};
+use hack_emulator::testscript::TestScript;
+
+const USAGE: &str = "Usage: hack_test SCRIPT.tst";
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        cli::usage_error("expected a script", USAGE);
+    }
+    let filename = &args[1];
+    let source = fs::read_to_string(filename)
+        .unwrap_or_else(|err| fail(&format!("Failed reading {}", filename), err));
+    let script = TestScript::parse(&source)
+        .unwrap_or_else(|err| fail(&format!("Couldn't parse {}", filename), err));
+
+    // the script's files are next to it, as with the course tools
+    let dir = Path::new(filename)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let outcome = script
+        .run(dir)
+        .unwrap_or_else(|err| fail("Script error", err));
+    if let Some(output_file) = &outcome.output_file {
+        if let Err(err) = fs::write(dir.join(output_file), &outcome.output) {
+            fail("Failed writing file", err);
+        }
+    }
+    match outcome.mismatch {
+        Some(mismatch) => {
+            println!("Comparison failure at {}", mismatch);
+            process::exit(1);
+        }
+        None if outcome.compare_to.is_some() => {
+            println!("End of script - Comparison ended successfully")
+        }
+        None => println!("End of script"),
+    }
+}
@@ -0,0 +1,93 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::process;
+
+use hack_emulator::cli::{self, fail};
+use hack_emulator::trace::{self, Format, TraceReader, TraceWriter};
+use hack_emulator::Computer;
+
+const USAGE: &str = "Usage: hack_trace PROGRAM.hack [--cycles N] [--format csv|binary] [--out FILE]
+       hack_trace --diff LEFT RIGHT [--writes]";
+
+struct Options {
+    program: String,
+    cycles: u64,
+    format: Format,
+    out: Option<String>,
+    diff: Option<(String, String)>,
+    writes: bool,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        program: String::new(),
+        cycles: 1_000_000,
+        format: Format::Csv,
+        out: None,
+        diff: None,
+        writes: false,
+    };
+    let mut args = cli::Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg {
+            "--cycles" => options.cycles = args.parse(arg)?,
+            "--format" => {
+                options.format = match args.value(arg)?.as_str() {
+                    "csv" => Format::Csv,
+                    "binary" => Format::Binary,
+                    other => return Err(format!("unsupported format {}", other)),
+                }
+            }
+            "--out" => options.out = Some(args.value(arg)?),
+            "--diff" => options.diff = Some((args.value(arg)?, args.value(arg)?)),
+            "--writes" => options.writes = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => options.program = arg.to_string(),
+        }
+    }
+    if options.program.is_empty() && options.diff.is_none() {
+        return Err(String::from("not enough arguments"));
+    }
+    Ok(options)
+}
+
+fn open(filename: &str) -> TraceReader<BufReader<File>> {
+    File::open(filename)
+        .map_err(|err| err.into())
+        .and_then(|file| TraceReader::new(BufReader::new(file)))
+        .unwrap_or_else(|err| fail(&format!("Failed reading {}", filename), err))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_options(&args).unwrap_or_else(|err| cli::usage_error(err, USAGE));
+
+    if let Some((left, right)) = &options.diff {
+        match trace::diff(open(left), open(right), options.writes) {
+            Ok(None) => println!("Traces match"),
+            Ok(Some(divergence)) => {
+                println!("{}", divergence);
+                process::exit(1);
+            }
+            Err(err) => fail("Failed reading trace", err),
+        }
+        return;
+    }
+
+    let program = hack_emulator::read_hack(&options.program)
+        .unwrap_or_else(|err| fail("Application error", err));
+    let mut computer = Computer::from_program(&program);
+    let out: Box<dyn Write> = match &options.out {
+        Some(filename) => Box::new(
+            File::create(filename)
+                .unwrap_or_else(|err| fail(&format!("Failed creating {}", filename), err)),
+        ),
+        None => Box::new(io::stdout()),
+    };
+    let result = TraceWriter::new(BufWriter::new(out), options.format)
+        .and_then(|mut writer| trace::trace(&mut computer, options.cycles, &mut writer));
+    if let Err(err) = result {
+        fail("Failed writing trace", err);
+    }
+}
@@ -0,0 +1,59 @@
+//! Argument and error handling shared by the command line tools.
+use std::fmt::Display;
+use std::process;
+use std::slice;
+use std::str::FromStr;
+
+/// The arguments after the program name, handing out the values that
+/// follow options such as `--cycles N`.
+pub struct Args<'a> {
+    args: slice::Iter<'a, String>,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(args: &'a [String]) -> Args<'a> {
+        Args {
+            args: args.get(1..).unwrap_or(&[]).iter(),
+        }
+    }
+
+    /// The value following `option`.
+    pub fn value(&mut self, option: &str) -> Result<String, String> {
+        self.args
+            .next()
+            .cloned()
+            .ok_or_else(|| format!("missing value for {}", option))
+    }
+
+    /// The value following `option`, parsed as a number or the like.
+    pub fn parse<T>(&mut self, option: &str) -> Result<T, String>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.value(option)?
+            .parse()
+            .map_err(|err| format!("{} for {}", err, option))
+    }
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.args.next().map(String::as_str)
+    }
+}
+
+/// Reports bad arguments along with the usage on stderr and exits.
+pub fn usage_error(err: impl Display, usage: &str) -> ! {
+    eprintln!("Problem parsing arguments: {}", err);
+    eprintln!("{}", usage);
+    process::exit(1);
+}
+
+/// Reports `context: err` on stderr and exits.
+pub fn fail(context: &str, err: impl Display) -> ! {
+    eprintln!("{}: {}", context, err);
+    process::exit(1);
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+
+/// A cell of an output table that doesn't match the compare file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    /// The line in the files, counted from 1.
+    pub row: usize,
+    /// The column's name from the compare file's header, None if the whole
+    /// line is missing or has a different number of columns.
+    pub column: Option<String>,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.column {
+            Some(column) => write!(
+                f,
+                "line {}, column {}: expected `{}`, got `{}`",
+                self.row, column, self.expected, self.actual
+            ),
+            None => write!(
+                f,
+                "line {}: expected `{}`, got `{}`",
+                self.row, self.expected, self.actual
+            ),
+        }
+    }
+}
+
+fn cells(line: &str) -> Vec<&str> {
+    line.split('|').collect()
+}
+
+/// Whether a cell matches its compare cell, where `*` stands for any
+/// character and a cell of only `*` for any value.
+fn matches(actual: &str, expected: &str) -> bool {
+    let trimmed = expected.trim();
+    if !trimmed.is_empty() && trimmed.chars().all(|c| c == '*') {
+        return true;
+    }
+    actual.chars().count() == expected.chars().count()
+        && actual
+            .chars()
+            .zip(expected.chars())
+            .all(|(a, e)| e == '*' || a == e)
+}
+
+/// Compares an output table to a compare file cell by cell, the columns
+/// separated by `|`. Trailing whitespace and line endings don't matter.
+pub fn compare(output: &str, expected: &str) -> Vec<Mismatch> {
+    let output: Vec<&str> = output.lines().map(|line| line.trim_end()).collect();
+    let expected: Vec<&str> = expected.lines().map(|line| line.trim_end()).collect();
+    let header = expected.first().map(|line| cells(line)).unwrap_or_default();
+    let mut mismatches = Vec::new();
+    for row in 0..output.len().max(expected.len()) {
+        let (actual, wanted) = match (output.get(row), expected.get(row)) {
+            (Some(actual), Some(wanted)) => (*actual, *wanted),
+            (actual, wanted) => {
+                mismatches.push(Mismatch {
+                    row: row + 1,
+                    column: None,
+                    expected: wanted.unwrap_or(&"").to_string(),
+                    actual: actual.unwrap_or(&"").to_string(),
+                });
+                continue;
+            }
+        };
+        let (actual_cells, wanted_cells) = (cells(actual), cells(wanted));
+        if actual_cells.len() != wanted_cells.len() {
+            mismatches.push(Mismatch {
+                row: row + 1,
+                column: None,
+                expected: wanted.to_string(),
+                actual: actual.to_string(),
+            });
+            continue;
+        }
+        for (i, (actual, wanted)) in actual_cells.iter().zip(&wanted_cells).enumerate() {
+            if !matches(actual, wanted) {
+                let column = header
+                    .get(i)
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| format!("{}", i));
+                mismatches.push(Mismatch {
+                    row: row + 1,
+                    column: Some(column),
+                    expected: wanted.trim().to_string(),
+                    actual: actual.trim().to_string(),
+                });
+            }
+        }
+    }
+    mismatches
+}
@@ -0,0 +1,248 @@
+use crate::program::Program;
+use crate::timetravel::{Recorder, Snapshot};
+use crate::{BoxResult, Computer};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Why execution stopped after a debugger command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// The requested step or run-to target was reached.
+    Done,
+    Breakpoint(u16),
+    /// The RAM address was written, or when running backwards is about to be.
+    Watchpoint(u16),
+    Halted,
+    /// The cycle limit ran out first.
+    Limit,
+    /// Running backwards reached the oldest recorded instruction.
+    HistoryStart,
+}
+
+/// Steps a program assembled from source forwards and backwards, stopping at
+/// breakpoints that are set by label name or ROM address and at watchpoints
+/// on RAM.
+pub struct Debugger {
+    recorder: Recorder,
+    program: Program,
+    breakpoints: BTreeSet<u16>,
+    watches: Vec<(String, u16)>,
+}
+
+impl Debugger {
+    pub fn new(source: &str) -> BoxResult<Self> {
+        let program = Program::assemble(source)?;
+        let recorder = Recorder::new(Computer::from_program(program.words()));
+        Ok(Self {
+            recorder,
+            program,
+            breakpoints: BTreeSet::new(),
+            watches: Vec::new(),
+        })
+    }
+
+    pub fn computer(&self) -> &Computer {
+        self.recorder.computer()
+    }
+
+    /// Changes made here are not recorded and can't be stepped back.
+    pub fn computer_mut(&mut self) -> &mut Computer {
+        self.recorder.computer_mut()
+    }
+
+    pub fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Starts over with cleared registers and memory, keeping breakpoints,
+    /// watches and watchpoints.
+    pub fn restart(&mut self) {
+        let snapshot = Snapshot::of(&Computer::from_program(self.program.words()));
+        self.recorder.restore(&snapshot);
+    }
+
+    /// Resolves a label name or a ROM address.
+    pub fn location(&self, location: &str) -> BoxResult<u16> {
+        if let Ok(address) = location.parse::<u16>() {
+            return Ok(address);
+        }
+        match self.program.label_address(location) {
+            Some(address) => Ok(address),
+            None => bail!("Unknown label `{}`.", location),
+        }
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, location: &str) -> BoxResult<u16> {
+        let address = self.location(location)?;
+        self.breakpoints.insert(address);
+        Ok(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, location: &str) -> BoxResult<u16> {
+        let address = self.location(location)?;
+        if !self.breakpoints.remove(&address) {
+            bail!("No breakpoint at `{}`.", location);
+        }
+        Ok(address)
+    }
+
+    /// Sets the breakpoint if there is none at the location, otherwise
+    /// removes it. Returns true if the breakpoint is now set.
+    pub fn toggle_breakpoint(&mut self, location: &str) -> BoxResult<bool> {
+        let address = self.location(location)?;
+        if self.breakpoints.remove(&address) {
+            Ok(false)
+        } else {
+            self.breakpoints.insert(address);
+            Ok(true)
+        }
+    }
+
+    /// Resolves a RAM address given as a number or a symbol, e.g. `sum` or `R2`.
+    pub fn variable(&self, target: &str) -> BoxResult<u16> {
+        match target.parse::<u16>() {
+            Ok(address) => Ok(address),
+            Err(_) => match self.program.symbol(target) {
+                Some(address) if self.program.label_address(target).is_none() => Ok(address),
+                _ => bail!("Unknown variable `{}`.", target),
+            },
+        }
+    }
+
+    /// Shows the value of a RAM address given as a number or a symbol.
+    pub fn add_watch(&mut self, target: &str) -> BoxResult<u16> {
+        let address = self.variable(target)?;
+        if !self.watches.iter().any(|(name, _)| name == target) {
+            self.watches.push((target.to_string(), address));
+        }
+        Ok(address)
+    }
+
+    /// The watched names with their RAM addresses, in the order added.
+    pub fn watches(&self) -> &[(String, u16)] {
+        &self.watches
+    }
+
+    /// Stops when a RAM address given as a number or a symbol is written.
+    /// Returns true if the watchpoint is now set, false if it was removed.
+    pub fn toggle_watchpoint(&mut self, target: &str) -> BoxResult<bool> {
+        let address = self.variable(target)?;
+        if self.recorder.remove_watchpoint(address) {
+            Ok(false)
+        } else {
+            self.recorder.add_watchpoint(address);
+            Ok(true)
+        }
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) -> Stop {
+        self.recorder.step()
+    }
+
+    /// Undoes the last instruction.
+    pub fn reverse_step(&mut self) -> Stop {
+        self.recorder.reverse_step()
+    }
+
+    /// Runs backwards until the instruction at a breakpoint or the last
+    /// write to a watchpoint is next.
+    pub fn reverse_resume(&mut self) -> Stop {
+        loop {
+            match self.recorder.reverse_step() {
+                Stop::Done => {
+                    let pc = self.computer().pc();
+                    if self.breakpoints.contains(&pc) {
+                        return Stop::Breakpoint(pc);
+                    }
+                }
+                stop => return stop,
+            }
+        }
+    }
+
+    pub fn save_snapshot(&self, path: &Path) -> BoxResult<()> {
+        self.recorder.snapshot().save(path)
+    }
+
+    pub fn load_snapshot(&mut self, path: &Path) -> BoxResult<()> {
+        let snapshot = Snapshot::load(path)?;
+        self.recorder.restore(&snapshot);
+        Ok(())
+    }
+
+    /// Like `step`, but a jump that is followed by a label, the return label
+    /// of a call, runs until control comes back after it. Recursive calls
+    /// through the same jump are counted, so only the outermost return stops.
+    pub fn step_over(&mut self, max_cycles: u64) -> Stop {
+        let pc = self.computer().pc();
+        let instruction = self.computer().rom()[pc as usize];
+        let is_jump = instruction & 0x8000 != 0 && instruction & 0b111 != 0;
+        let return_address = pc + 1;
+        let returns_here = self
+            .program
+            .labels()
+            .iter()
+            .any(|(_, address)| *address == return_address);
+        if !is_jump || !returns_here {
+            return self.step();
+        }
+        let mut depth = 0;
+        self.run_until(max_cycles, |from, computer| {
+            if from == pc && computer.pc() != return_address {
+                depth += 1;
+                false
+            } else if computer.pc() == return_address {
+                depth -= 1;
+                depth <= 0
+            } else {
+                false
+            }
+        })
+    }
+
+    /// Runs until a breakpoint is hit, a watchpoint is written or the program
+    /// halts.
+    pub fn resume(&mut self, max_cycles: u64) -> Stop {
+        self.run_until(max_cycles, |_, _| false)
+    }
+
+    /// Runs until the instruction at `label` is next, stopping earlier at
+    /// breakpoints.
+    pub fn run_to(&mut self, label: &str, max_cycles: u64) -> BoxResult<Stop> {
+        let target = self.location(label)?;
+        Ok(self.run_until(max_cycles, |_, computer| computer.pc() == target))
+    }
+
+    /// Steps until `done`, called with the previous PC and the computer,
+    /// returns true. Executes at least one instruction, so that continuing
+    /// from a breakpoint doesn't stop on it again.
+    fn run_until<F>(&mut self, max_cycles: u64, mut done: F) -> Stop
+    where
+        F: FnMut(u16, &Computer) -> bool,
+    {
+        for _ in 0..max_cycles {
+            let from = self.computer().pc();
+            match self.recorder.step() {
+                Stop::Done => {}
+                stop => return stop,
+            }
+            if done(from, self.recorder.computer()) {
+                return Stop::Done;
+            }
+            let pc = self.computer().pc();
+            if self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+        }
+        Stop::Limit
+    }
+}
@@ -0,0 +1,309 @@
+use crate::{Computer, KBD, ROM_SIZE};
+use std::convert::TryInto;
+
+/// The six ALU control bits turned into masks, so that computing takes no
+/// branches: zero and negate the inputs, then add or and them and negate
+/// the output, see projects/02/ALU.hdl.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alu {
+    keep_x: u16,
+    flip_x: u16,
+    keep_y: u16,
+    flip_y: u16,
+    add: u16,
+    flip_out: u16,
+}
+
+fn mask(bit: bool) -> u16 {
+    if bit {
+        0xffff
+    } else {
+        0
+    }
+}
+
+impl Alu {
+    pub fn from_control(control: u16) -> Self {
+        Self {
+            keep_x: !mask(control & 0b100000 != 0),
+            flip_x: mask(control & 0b010000 != 0),
+            keep_y: !mask(control & 0b001000 != 0),
+            flip_y: mask(control & 0b000100 != 0),
+            add: mask(control & 0b000010 != 0),
+            flip_out: mask(control & 0b000001 != 0),
+        }
+    }
+
+    #[inline(always)]
+    pub fn compute(self, x: u16, y: u16) -> u16 {
+        let x = (x & self.keep_x) ^ self.flip_x;
+        let y = (y & self.keep_y) ^ self.flip_y;
+        let out = (x.wrapping_add(y) & self.add) | (x & y & !self.add);
+        out ^ self.flip_out
+    }
+}
+
+/// A ROM word decoded once, so that running doesn't pick instructions apart
+/// on every cycle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// `@value`
+    Load(u16),
+    /// A C-instruction. `dest` holds the A, D, M bits and `jump` the
+    /// lt, eq, gt bits as in the instruction word.
+    Compute {
+        alu: Alu,
+        m: bool,
+        dest: u8,
+        jump: u8,
+    },
+    /// An A-instruction followed by a C-instruction, the common case,
+    /// executed as one op taking two cycles.
+    LoadCompute {
+        value: u16,
+        alu: Alu,
+        m: bool,
+        dest: u8,
+        jump: u8,
+    },
+    /// The `(END) @END 0;JMP` loop, see `Computer::is_halted`.
+    Halt,
+}
+
+const DEST_A: u8 = 0b100;
+const DEST_D: u8 = 0b010;
+const DEST_M: u8 = 0b001;
+
+fn decode_word(word: u16) -> Op {
+    if word & 0x8000 == 0 {
+        return Op::Load(word);
+    }
+    Op::Compute {
+        alu: Alu::from_control((word >> 6) & 0b111111),
+        m: word & 0x1000 != 0,
+        dest: ((word >> 3) & 0b111) as u8,
+        jump: (word & 0b111) as u8,
+    }
+}
+
+/// Decodes every ROM word. Where an A-instruction is followed by a
+/// C-instruction the pair is fused into a `LoadCompute`; the op for the
+/// C-instruction itself stays in place for jumps that land on it.
+pub fn decode(rom: &[u16]) -> Vec<Op> {
+    let mut ops: Vec<Op> = rom.iter().map(|word| decode_word(*word)).collect();
+    for address in 0..ops.len() {
+        let next = ops.get(address + 1).cloned();
+        ops[address] = match (ops[address], next) {
+            // 0b1110101010000111 is `0;JMP`
+            (Op::Load(value), _)
+                if value as usize == address
+                    && rom.get(address + 1) == Some(&0b1110101010000111) =>
+            {
+                Op::Halt
+            }
+            (Op::Load(value), Some(Op::Compute { alu, m, dest, jump })) => Op::LoadCompute {
+                value,
+                alu,
+                m,
+                dest,
+                jump,
+            },
+            (op, _) => op,
+        };
+    }
+    ops
+}
+
+/// An op in the one shape the run loop executes: load A if `load` is set,
+/// then compute if `compute` is set, so no op needs a match to run. Each
+/// half advances the program counter by one, which keeps it independent of
+/// the op table while running.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Lowered {
+    load: bool,
+    compute: bool,
+    value: u16,
+    alu: Alu,
+    m: bool,
+    dest: u8,
+    jump: u8,
+    /// The instructions the op executes: 1, 2 for a fused `LoadCompute` and
+    /// 0 on `Halt`.
+    cycles: u8,
+    /// The op ends a basic block: it may jump, or the next op halts or lies
+    /// past the end of ROM.
+    last: bool,
+    /// The cycles from here to the end of the basic block, 0 on `Halt`.
+    block: u32,
+}
+
+const HALT: Lowered = Lowered {
+    load: false,
+    compute: false,
+    value: 0,
+    alu: Alu {
+        keep_x: 0xffff,
+        flip_x: 0,
+        keep_y: 0xffff,
+        flip_y: 0,
+        add: 0,
+        flip_out: 0,
+    },
+    m: false,
+    dest: 0,
+    jump: 0,
+    cycles: 0,
+    last: true,
+    block: 0,
+};
+
+fn lower(op: Op) -> Lowered {
+    let (load, compute, value, alu, m, dest, jump) = match op {
+        Op::Load(value) => (true, false, value, HALT.alu, false, 0, 0),
+        Op::Compute { alu, m, dest, jump } => (false, true, 0, alu, m, dest, jump),
+        Op::LoadCompute {
+            value,
+            alu,
+            m,
+            dest,
+            jump,
+        } => (true, true, value, alu, m, dest, jump),
+        Op::Halt => return HALT,
+    };
+    Lowered {
+        load,
+        compute,
+        value,
+        alu,
+        m,
+        dest,
+        jump,
+        cycles: load as u8 + compute as u8,
+        last: jump != 0,
+        block: 0,
+    }
+}
+
+/// Lowers the decoded ROM and sizes the basic block starting at every
+/// address, working backwards so each block extends the one after it.
+pub(crate) fn lower_rom(rom: &[u16]) -> Box<[Lowered; ROM_SIZE]> {
+    let ops = decode(rom);
+    let mut lowered: Box<[Lowered; ROM_SIZE]> =
+        vec![HALT; ROM_SIZE].into_boxed_slice().try_into().unwrap();
+    for address in (0..ROM_SIZE).rev() {
+        let mut op = lower(ops.get(address).cloned().unwrap_or(Op::Load(0)));
+        if op.cycles == 0 {
+            continue;
+        }
+        let next = address + op.cycles as usize;
+        op.last |= next >= ROM_SIZE || lowered[next].cycles == 0;
+        op.block = op.cycles as u32 + if op.last { 0 } else { lowered[next].block };
+        lowered[address] = op;
+    }
+    lowered
+}
+
+/// The CPU registers, kept in locals while running so they can live in
+/// machine registers.
+struct Registers {
+    a: u16,
+    d: u16,
+    pc: u16,
+}
+
+impl Registers {
+    #[inline(always)]
+    fn load(&mut self, op: &Lowered) {
+        if op.load {
+            self.a = op.value;
+            self.pc = (self.pc + 1) & 0x7fff;
+        }
+    }
+
+    #[inline(always)]
+    fn execute(&mut self, ram: &mut [u16; ROM_SIZE], op: &Lowered) {
+        self.load(op);
+        if !op.compute {
+            return;
+        }
+        let address = (self.a & 0x7fff) as usize;
+        let y = if op.m { ram[address] } else { self.a };
+        let out = op.alu.compute(self.d, y);
+        if op.dest & DEST_M != 0 && address < KBD {
+            ram[address] = out;
+        }
+        if op.dest & DEST_D != 0 {
+            self.d = out;
+        }
+        let target = self.a & 0x7fff;
+        if op.dest & DEST_A != 0 {
+            self.a = out;
+        }
+        self.pc = (self.pc + 1) & 0x7fff;
+        if op.jump != 0 {
+            // the lt, eq, gt bit that holds for out
+            let negative = (out >> 15) as u8;
+            let zero = (out == 0) as u8;
+            let condition = negative << 2 | zero << 1 | (1 ^ negative ^ zero);
+            if op.jump & condition != 0 {
+                self.pc = target;
+            }
+        }
+    }
+}
+
+impl Computer {
+    /// Runs the predecoded ops, with the same results as calling `step`
+    /// until halted or `max_cycles` instructions have been executed.
+    pub(crate) fn run_decoded(&mut self, max_cycles: u64) -> u64 {
+        let ops = match self.ops.take() {
+            Some(ops) => ops,
+            None => lower_rom(&self.rom),
+        };
+        let mut registers = Registers {
+            a: self.a,
+            d: self.d,
+            pc: self.pc,
+        };
+        let ram = &mut self.ram;
+        let mut remaining = max_cycles;
+        loop {
+            let block = ops[registers.pc as usize].block as u64;
+            if block == 0 {
+                break;
+            }
+            if block <= remaining {
+                // the whole block fits, so only its end needs checking
+                remaining -= block;
+                loop {
+                    let op = &ops[registers.pc as usize];
+                    registers.execute(ram, op);
+                    if op.last {
+                        break;
+                    }
+                }
+                continue;
+            }
+            // the cycle limit falls inside this block, possibly between the
+            // two instructions of a fused pair
+            while remaining > 0 {
+                let op = &ops[registers.pc as usize];
+                if op.cycles as u64 > remaining {
+                    registers.load(op);
+                    remaining = 0;
+                } else {
+                    registers.execute(ram, op);
+                    remaining -= op.cycles as u64;
+                }
+            }
+            break;
+        }
+        self.a = registers.a;
+        self.d = registers.d;
+        self.pc = registers.pc;
+        self.ops = Some(ops);
+        let executed = max_cycles - remaining;
+        self.cycles += executed;
+        executed
+    }
+}
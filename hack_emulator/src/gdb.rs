@@ -0,0 +1,555 @@
+//! A GDB Remote Serial Protocol stub, so that debuggers speaking the
+//! protocol can drive the Hack computer.
+//!
+//! Memory is byte addressed for GDB: RAM word `n` is at bytes `2n` and
+//! `2n + 1` in little-endian order, ROM word `n` at `ROM_BASE + 2n`. The
+//! registers are `pc`, a pointer into ROM, followed by `a` and `d`.
+
+use crate::Computer;
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+pub const ROM_BASE: u32 = 0x10000;
+/// Instructions run between checks for an interrupt from the debugger.
+const CHUNK: u64 = 100_000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nand2tetris.hack">
+    <reg name="pc" bitsize="32" type="code_ptr" regnum="0"/>
+    <reg name="a" bitsize="16" type="uint16" regnum="1"/>
+    <reg name="d" bitsize="16" type="int16" regnum="2"/>
+  </feature>
+</target>
+"#;
+
+pub fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte))
+}
+
+/// Wraps the data into a `$data#checksum` packet.
+pub fn frame(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(data: &str) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(data.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parses `ADDR,LENGTH` as sent with memory and breakpoint packets. Ranges
+/// that run past the 32-bit address space are rejected, so the end of a
+/// parsed range can be computed without overflow.
+fn address_length(args: &str) -> Option<(u32, u32)> {
+    let mut fields = args.splitn(2, ',');
+    let address = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let length = u32::from_str_radix(fields.next()?, 16).ok()?;
+    address.checked_add(length.max(1))?;
+    Some((address, length))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Watchpoint {
+    kind: WatchKind,
+    /// The watched byte range.
+    address: u32,
+    length: u32,
+}
+
+/// What the connection should do after a packet was handled.
+#[derive(Debug, PartialEq)]
+pub enum Response {
+    Reply(String),
+    /// Console output for a `monitor` command, sent before the reply.
+    Output(Vec<String>, String),
+    /// Run until `run` reports a stop.
+    Resume,
+    /// Reply, then close the connection.
+    Close(Option<String>),
+}
+
+/// The debugger state behind a connection: the computer, breakpoints in ROM
+/// and watchpoints on RAM.
+pub struct GdbStub {
+    computer: Computer,
+    labels: Vec<(String, u16)>,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl GdbStub {
+    /// `labels` are the ROM addresses available to `monitor break`.
+    pub fn new(computer: Computer, labels: Vec<(String, u16)>) -> Self {
+        Self {
+            computer,
+            labels,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+        }
+    }
+
+    pub fn computer(&self) -> &Computer {
+        &self.computer
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    fn registers(&self) -> String {
+        let pc = ROM_BASE + 2 * self.computer.pc() as u32;
+        format!(
+            "{}{}{}",
+            hex(&pc.to_le_bytes()),
+            hex(&self.computer.a().to_le_bytes()),
+            hex(&self.computer.d().to_le_bytes())
+        )
+    }
+
+    fn set_register(&mut self, number: usize, bytes: &[u8]) -> Option<()> {
+        match number {
+            0 => {
+                let pc = u32::from_le_bytes([
+                    *bytes.first()?,
+                    *bytes.get(1)?,
+                    *bytes.get(2)?,
+                    *bytes.get(3)?,
+                ]);
+                let word = pc.checked_sub(ROM_BASE)? / 2;
+                self.computer.set_pc((word & 0x7fff) as u16);
+            }
+            1 => self
+                .computer
+                .set_a(u16::from_le_bytes([*bytes.first()?, *bytes.get(1)?])),
+            2 => self
+                .computer
+                .set_d(u16::from_le_bytes([*bytes.first()?, *bytes.get(1)?])),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn read_byte(&self, address: u32) -> Option<u8> {
+        let word = if address < ROM_BASE {
+            self.computer.read((address / 2) as u16)
+        } else {
+            *self
+                .computer
+                .rom()
+                .get(((address - ROM_BASE) / 2) as usize)?
+        };
+        Some(word.to_le_bytes()[(address % 2) as usize])
+    }
+
+    fn write_byte(&mut self, address: u32, byte: u8) -> Option<()> {
+        let index = (address % 2) as usize;
+        if address < ROM_BASE {
+            let word = (address / 2) as u16;
+            let mut bytes = self.computer.read(word).to_le_bytes();
+            bytes[index] = byte;
+            self.computer.write(word, u16::from_le_bytes(bytes));
+        } else {
+            let word = self
+                .computer
+                .rom_mut()
+                .get_mut(((address - ROM_BASE) / 2) as usize)?;
+            let mut bytes = word.to_le_bytes();
+            bytes[index] = byte;
+            *word = u16::from_le_bytes(bytes);
+        }
+        Some(())
+    }
+
+    fn rom_address(address: u32) -> Option<u16> {
+        let word = address.checked_sub(ROM_BASE)? / 2;
+        if word < 0x8000 {
+            Some(word as u16)
+        } else {
+            None
+        }
+    }
+
+    fn label(&self, name: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(label, _)| label == name)
+            .map(|(_, address)| *address)
+    }
+
+    /// Runs a `monitor` command and returns its console output.
+    fn monitor(&mut self, command: &str) -> String {
+        let fields: Vec<&str> = command.split_whitespace().collect();
+        match fields.as_slice() {
+            ["break", name] => match self.label(name) {
+                Some(address) => {
+                    self.breakpoints.insert(address);
+                    format!(
+                        "Breakpoint at {} ({:#x})\n",
+                        name,
+                        ROM_BASE + 2 * address as u32
+                    )
+                }
+                None => format!("Unknown label `{}`.\n", name),
+            },
+            ["delete", name] => match self.label(name) {
+                Some(address) if self.breakpoints.remove(&address) => {
+                    format!("Deleted breakpoint at {}\n", name)
+                }
+                Some(_) => format!("No breakpoint at {}\n", name),
+                None => format!("Unknown label `{}`.\n", name),
+            },
+            ["labels"] => self
+                .labels
+                .iter()
+                .map(|(name, address)| {
+                    format!("{:#07x} {}\n", ROM_BASE + 2 * *address as u32, name)
+                })
+                .collect(),
+            ["reset"] => {
+                self.computer.reset();
+                String::from("PC set to 0\n")
+            }
+            _ => String::from("Commands: break LABEL, delete LABEL, labels, reset\n"),
+        }
+    }
+
+    /// Handles a packet's data and tells what to send back.
+    pub fn handle(&mut self, packet: &str) -> Response {
+        let reply = |data: &str| Response::Reply(data.to_string());
+        // the command is the first character, which needn't be ASCII
+        let first = packet.chars().next().map_or(0, char::len_utf8);
+        let (command, args) = packet.split_at(first);
+        match command {
+            "?" => reply("S05"),
+            "g" => Response::Reply(self.registers()),
+            "G" => match unhex(args) {
+                Some(bytes) if bytes.len() >= 8 => {
+                    let set = self
+                        .set_register(0, &bytes[0..4])
+                        .and_then(|_| self.set_register(1, &bytes[4..6]))
+                        .and_then(|_| self.set_register(2, &bytes[6..8]));
+                    reply(if set.is_some() { "OK" } else { "E01" })
+                }
+                _ => reply("E01"),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(0) => Response::Reply(self.registers()[0..8].to_string()),
+                Ok(1) => Response::Reply(self.registers()[8..12].to_string()),
+                Ok(2) => Response::Reply(self.registers()[12..16].to_string()),
+                _ => reply("E01"),
+            },
+            "P" => {
+                let mut fields = args.splitn(2, '=');
+                let number = fields
+                    .next()
+                    .and_then(|n| usize::from_str_radix(n, 16).ok());
+                let bytes = fields.next().and_then(unhex);
+                match (number, bytes) {
+                    (Some(number), Some(bytes)) if self.set_register(number, &bytes).is_some() => {
+                        reply("OK")
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            "m" => match address_length(args) {
+                Some((address, length)) => {
+                    let bytes: Option<Vec<u8>> = (address..address + length)
+                        .map(|address| self.read_byte(address))
+                        .collect();
+                    match bytes {
+                        Some(bytes) => Response::Reply(hex(&bytes)),
+                        None => reply("E14"),
+                    }
+                }
+                None => reply("E01"),
+            },
+            "M" => {
+                let mut fields = args.splitn(2, ':');
+                let target = fields.next().and_then(address_length);
+                let bytes = fields.next().and_then(unhex);
+                match (target, bytes) {
+                    (Some((address, length)), Some(bytes)) if bytes.len() == length as usize => {
+                        let written = bytes
+                            .iter()
+                            .enumerate()
+                            .all(|(i, byte)| self.write_byte(address + i as u32, *byte).is_some());
+                        reply(if written { "OK" } else { "E14" })
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            "c" | "s" => {
+                if let Some(pc) = u32::from_str_radix(args, 16)
+                    .ok()
+                    .and_then(Self::rom_address)
+                {
+                    self.computer.set_pc(pc);
+                }
+                if command == "s" {
+                    Response::Reply(self.step().unwrap_or_else(|| String::from("S05")))
+                } else {
+                    Response::Resume
+                }
+            }
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "H" => reply("OK"),
+            "T" => reply("OK"),
+            "k" => Response::Close(None),
+            "D" => Response::Close(Some(String::from("OK"))),
+            _ => self.query(packet),
+        }
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Response {
+        let mut fields = args.splitn(2, ',');
+        let kind = fields.next().unwrap_or("");
+        let (address, length) = match fields.next().and_then(address_length) {
+            Some(target) => target,
+            None => return Response::Reply(String::from("E01")),
+        };
+        let watch = match kind {
+            "0" | "1" => {
+                return match Self::rom_address(address) {
+                    Some(address) => {
+                        if insert {
+                            self.breakpoints.insert(address);
+                        } else {
+                            self.breakpoints.remove(&address);
+                        }
+                        Response::Reply(String::from("OK"))
+                    }
+                    None => Response::Reply(String::from("E01")),
+                };
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Response::Reply(String::new()),
+        };
+        if address >= ROM_BASE {
+            return Response::Reply(String::from("E01"));
+        }
+        let watchpoint = Watchpoint {
+            kind: watch,
+            address,
+            length: length.max(1),
+        };
+        if insert {
+            self.watchpoints.push(watchpoint);
+        } else {
+            self.watchpoints.retain(|existing| *existing != watchpoint);
+        }
+        Response::Reply(String::from("OK"))
+    }
+
+    fn query(&mut self, packet: &str) -> Response {
+        let reply = |data: &str| Response::Reply(data.to_string());
+        if packet.starts_with("qSupported") {
+            reply("PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+")
+        } else if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            match address_length(args) {
+                Some((offset, length)) => {
+                    let start = (offset as usize).min(TARGET_XML.len());
+                    let end = (start + length as usize).min(TARGET_XML.len());
+                    let marker = if end == TARGET_XML.len() { "l" } else { "m" };
+                    Response::Reply(format!("{}{}", marker, &TARGET_XML[start..end]))
+                }
+                None => reply("E01"),
+            }
+        } else if let Some(command) = packet.strip_prefix("qRcmd,") {
+            match unhex(command) {
+                Some(bytes) => {
+                    let output = self.monitor(&String::from_utf8_lossy(&bytes));
+                    Response::Output(
+                        vec![format!("O{}", hex(output.as_bytes()))],
+                        String::from("OK"),
+                    )
+                }
+                None => reply("E01"),
+            }
+        } else {
+            match packet {
+                "qAttached" => reply("1"),
+                "qC" => reply("QC1"),
+                "qfThreadInfo" => reply("m1"),
+                "qsThreadInfo" => reply("l"),
+                // unsupported packets get an empty reply
+                _ => reply(""),
+            }
+        }
+    }
+
+    /// Executes one instruction and returns the stop reply if it hit a
+    /// watchpoint or the program has halted.
+    fn step(&mut self) -> Option<String> {
+        if self.computer.is_halted() {
+            return Some(String::from("W00"));
+        }
+        let instruction = self.computer.rom()[self.computer.pc() as usize];
+        // C-instructions with the a-bit set read M
+        let read = if instruction & 0x9000 == 0x9000 {
+            Some(self.computer.a() & 0x7fff)
+        } else {
+            None
+        };
+        let write = self.computer.step().map(|write| write.address);
+        for watchpoint in &self.watchpoints {
+            let (accessed, name) = match watchpoint.kind {
+                WatchKind::Write => (write, "watch"),
+                WatchKind::Read => (read, "rwatch"),
+                WatchKind::Access => (read.or(write), "awatch"),
+            };
+            if let Some(word) = accessed {
+                let address = 2 * word as u32;
+                if address + 1 >= watchpoint.address
+                    && address < watchpoint.address + watchpoint.length
+                {
+                    return Some(format!("T05{}:{:x};", name, address));
+                }
+            }
+        }
+        None
+    }
+
+    /// Continues for up to `max_cycles` instructions. Returns the stop reply
+    /// or None if the program is still running.
+    pub fn run(&mut self, max_cycles: u64) -> Option<String> {
+        for _ in 0..max_cycles {
+            if let Some(stop) = self.step() {
+                return Some(stop);
+            }
+            if self.breakpoints.contains(&self.computer.pc()) {
+                return Some(String::from("T05swbreak:;"));
+            }
+        }
+        None
+    }
+}
+
+/// Reads the next packet, acknowledging it. Returns None when the
+/// connection is closed; an interrupt outside of a run is ignored.
+fn read_packet<S: Read + Write>(stream: &mut S) -> io::Result<Option<String>> {
+    let mut byte = [0u8; 1];
+    loop {
+        if stream.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if byte[0] != b'$' {
+            continue;
+        }
+        let mut data = Vec::new();
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        let mut sum = [0u8; 2];
+        stream.read_exact(&mut sum)?;
+        // `}` escapes the next byte xor 0x20
+        let mut packet = Vec::new();
+        let mut escaped = false;
+        for byte in &data {
+            match (*byte, escaped) {
+                (b'}', false) => escaped = true,
+                (byte, true) => {
+                    packet.push(byte ^ 0x20);
+                    escaped = false;
+                }
+                (byte, false) => packet.push(byte),
+            }
+        }
+        let expected = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+            != Some(expected)
+        {
+            stream.write_all(b"-")?;
+            continue;
+        }
+        stream.write_all(b"+")?;
+        return Ok(Some(String::from_utf8_lossy(&packet).to_string()));
+    }
+}
+
+/// Sends a packet and waits for its acknowledgement, resending on `-`.
+fn send<S: Read + Write>(stream: &mut S, data: &str) -> io::Result<()> {
+    let packet = frame(data);
+    loop {
+        stream.write_all(packet.as_bytes())?;
+        stream.flush()?;
+        let mut ack = [0u8; 1];
+        if stream.read(&mut ack)? == 0 || ack[0] != b'-' {
+            return Ok(());
+        }
+    }
+}
+
+/// Checks without blocking whether the debugger sent an interrupt (^C).
+fn interrupted(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0u8; 1];
+    let result = match stream.read(&mut byte) {
+        Ok(1) => Ok(byte[0] == 0x03),
+        Ok(_) => Ok(false),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(err) => Err(err),
+    };
+    stream.set_nonblocking(false)?;
+    result
+}
+
+/// Serves one debugger connection until it detaches, kills the program or
+/// disconnects.
+pub fn serve(stub: &mut GdbStub, mut stream: TcpStream) -> io::Result<()> {
+    while let Some(packet) = read_packet(&mut stream)? {
+        match stub.handle(&packet) {
+            Response::Reply(data) => send(&mut stream, &data)?,
+            Response::Output(lines, data) => {
+                for line in lines {
+                    send(&mut stream, &line)?;
+                }
+                send(&mut stream, &data)?;
+            }
+            Response::Resume => loop {
+                if let Some(stop) = stub.run(CHUNK) {
+                    send(&mut stream, &stop)?;
+                    break;
+                }
+                if interrupted(&mut stream)? {
+                    send(&mut stream, "T02")?;
+                    break;
+                }
+            },
+            Response::Close(data) => {
+                if let Some(data) = data {
+                    send(&mut stream, &data)?;
+                }
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use crate::{BoxResult, Computer};
+
+/// Key codes of the special keys, as defined for the Hack keyboard.
+pub const NEWLINE: u16 = 128;
+pub const BACKSPACE: u16 = 129;
+pub const LEFT: u16 = 130;
+pub const UP: u16 = 131;
+pub const RIGHT: u16 = 132;
+pub const DOWN: u16 = 133;
+pub const HOME: u16 = 134;
+pub const END: u16 = 135;
+pub const PAGE_UP: u16 = 136;
+pub const PAGE_DOWN: u16 = 137;
+pub const INSERT: u16 = 138;
+pub const DELETE: u16 = 139;
+pub const ESC: u16 = 140;
+/// F1 to F12 are numbered consecutively from here.
+pub const F1: u16 = 141;
+
+/// Translates a key name (`UP`, `newline`, `F5`, `space`, `none`), a numeric
+/// code or a single printable character into its Hack key code. Numbers take
+/// precedence, so the digit keys are given by their codes 48 to 57.
+pub fn key_code(name: &str) -> BoxResult<u16> {
+    let code = match name.to_uppercase().as_str() {
+        "NONE" | "RELEASE" => 0,
+        "SPACE" => 32,
+        "NEWLINE" | "ENTER" => NEWLINE,
+        "BACKSPACE" => BACKSPACE,
+        "LEFT" => LEFT,
+        "UP" => UP,
+        "RIGHT" => RIGHT,
+        "DOWN" => DOWN,
+        "HOME" => HOME,
+        "END" => END,
+        "PAGEUP" | "PAGE_UP" => PAGE_UP,
+        "PAGEDOWN" | "PAGE_DOWN" => PAGE_DOWN,
+        "INSERT" => INSERT,
+        "DELETE" => DELETE,
+        "ESC" | "ESCAPE" => ESC,
+        upper => {
+            if let Ok(code) = name.parse::<u16>() {
+                code
+            } else if let Some(n) = upper.strip_prefix('F').and_then(|n| n.parse::<u16>().ok()) {
+                if !(1..=12).contains(&n) {
+                    bail!("Unknown function key `{}`.", name);
+                }
+                F1 + n - 1
+            } else if name.len() == 1 && name.is_ascii() {
+                name.as_bytes()[0] as u16
+            } else {
+                bail!("Unknown key `{}`.", name)
+            }
+        }
+    };
+    Ok(code)
+}
+
+/// Sets the keyboard register to `code` once `cycle` instructions have run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub cycle: u64,
+    pub code: u16,
+}
+
+/// A timed sequence of key presses and releases replayed into KBD.
+#[derive(Debug, Default, PartialEq)]
+pub struct KeyScript {
+    events: Vec<KeyEvent>,
+}
+
+impl KeyScript {
+    pub fn new(mut events: Vec<KeyEvent>) -> Self {
+        events.sort_by_key(|event| event.cycle);
+        Self { events }
+    }
+
+    /// Parses one `CYCLE KEY` pair per line, e.g. `1000 UP` or `2000 0`.
+    /// Blank lines and `//` comments are ignored.
+    pub fn parse(content: &str) -> BoxResult<Self> {
+        let mut events = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split("//").next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 {
+                bail!("line {}: expected `CYCLE KEY`, found `{}`", i + 1, line);
+            }
+            let cycle = fields[0]
+                .parse::<u64>()
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+            let code = key_code(fields[1]).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            events.push(KeyEvent { cycle, code });
+        }
+        Ok(Self::new(events))
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    /// The key held down at `cycle`, or None before the first event.
+    pub fn key_at(&self, cycle: u64) -> Option<u16> {
+        let index = self.events.partition_point(|event| event.cycle <= cycle);
+        if index == 0 {
+            None
+        } else {
+            Some(self.events[index - 1].code)
+        }
+    }
+
+    fn next_event_after(&self, cycle: u64) -> Option<u64> {
+        let index = self.events.partition_point(|event| event.cycle <= cycle);
+        self.events.get(index).map(|event| event.cycle)
+    }
+
+    /// Runs like `Computer::run` while replaying the key events due in the
+    /// executed cycles. Can be called repeatedly to run in chunks.
+    pub fn run(&self, computer: &mut Computer, max_cycles: u64) -> u64 {
+        self.run_with(computer, max_cycles, |computer, cycles| {
+            computer.run(cycles)
+        })
+    }
+
+    /// Like `run`, but executes the cycles between key events with `run`,
+    /// which must behave like `Computer::run`.
+    pub fn run_with<F>(&self, computer: &mut Computer, max_cycles: u64, mut run: F) -> u64
+    where
+        F: FnMut(&mut Computer, u64) -> u64,
+    {
+        let end = computer.cycles() + max_cycles;
+        let mut executed = 0;
+        while computer.cycles() < end {
+            let now = computer.cycles();
+            if let Some(code) = self.key_at(now) {
+                computer.set_key(code);
+            }
+            let until = self.next_event_after(now).map_or(end, |next| next.min(end));
+            let ran = run(computer, until - now);
+            executed += ran;
+            if ran < until - now {
+                break;
+            }
+        }
+        executed
+    }
+}
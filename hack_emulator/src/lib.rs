@@ -0,0 +1,289 @@
+#[macro_use]
+extern crate simple_error;
+use std::error::Error;
+use std::fs;
+
+pub mod cli;
+pub mod compare;
+pub mod debugger;
+pub mod decode;
+pub mod gdb;
+pub mod keyboard;
+pub mod profiler;
+pub mod program;
+pub mod screen;
+pub mod testscript;
+pub mod timetravel;
+pub mod trace;
+pub mod tui;
+
+type BoxResult<T> = Result<T, Box<dyn Error>>;
+
+pub const ROM_SIZE: usize = 32768;
+/// Data memory: 16K of RAM followed by the screen map and the keyboard register.
+pub const RAM_SIZE: usize = KBD + 1;
+pub const SCREEN: usize = 16384;
+pub const SCREEN_SIZE: usize = 8192;
+pub const KBD: usize = 24576;
+
+/// A single write to data memory performed by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryWrite {
+    pub address: u16,
+    pub value: u16,
+}
+
+/// Computes the ALU output for the six control bits `zx nx zy ny f no`
+/// (instruction bits 11 to 6), see projects/02/ALU.hdl.
+pub fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let mut x = if control & 0b100000 != 0 { 0 } else { x };
+    if control & 0b010000 != 0 {
+        x = !x;
+    }
+    let mut y = if control & 0b001000 != 0 { 0 } else { y };
+    if control & 0b000100 != 0 {
+        y = !y;
+    }
+    let out = if control & 0b000010 != 0 {
+        x.wrapping_add(y)
+    } else {
+        x & y
+    };
+    if control & 0b000001 != 0 {
+        !out
+    } else {
+        out
+    }
+}
+
+/// Parses the text produced by `assembler::Parser::to_bytes`, one 16-bit
+/// binary word per line.
+pub fn parse_hack(content: &str) -> BoxResult<Vec<u16>> {
+    let mut program = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.len() != 16 {
+            bail!("line {}: expected 16 bits, found `{}`", i + 1, line);
+        }
+        match u16::from_str_radix(line, 2) {
+            Ok(word) => program.push(word),
+            Err(_) => bail!("line {}: invalid binary word `{}`", i + 1, line),
+        }
+    }
+    if program.len() > ROM_SIZE {
+        bail!(
+            "Program has {} words but the ROM holds {}.",
+            program.len(),
+            ROM_SIZE
+        );
+    }
+    Ok(program)
+}
+
+pub fn read_hack(filename: &str) -> BoxResult<Vec<u16>> {
+    let content = fs::read_to_string(filename)?;
+    parse_hack(&content)
+}
+
+/// The Hack computer: the CPU of projects/05/CPU.hdl with its instruction ROM
+/// and data memory including the memory-mapped screen and keyboard.
+pub struct Computer {
+    a: u16,
+    d: u16,
+    pc: u16,
+    rom: Vec<u16>,
+    /// Spans the whole 15-bit address space so that indexing with an address
+    /// needs no bounds check. Only the first `RAM_SIZE` words are memory,
+    /// the rest is never written and reads as 0.
+    ram: Box<[u16; ROM_SIZE]>,
+    cycles: u64,
+    /// The decoded ROM used by `run`, None until needed or after the ROM
+    /// changed.
+    ops: Option<Box<[decode::Lowered; ROM_SIZE]>>,
+}
+
+impl Default for Computer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Computer {
+    pub fn new() -> Self {
+        Self {
+            a: 0,
+            d: 0,
+            pc: 0,
+            rom: vec![0; ROM_SIZE],
+            ram: Box::new([0; ROM_SIZE]),
+            cycles: 0,
+            ops: None,
+        }
+    }
+
+    pub fn from_program(program: &[u16]) -> Self {
+        let mut computer = Self::new();
+        computer.load_rom(program);
+        computer
+    }
+
+    pub fn from_hack(content: &str) -> BoxResult<Self> {
+        Ok(Self::from_program(&parse_hack(content)?))
+    }
+
+    /// Replaces the ROM contents, the rest of the ROM is zeroed.
+    pub fn load_rom(&mut self, program: &[u16]) {
+        self.rom.iter_mut().for_each(|word| *word = 0);
+        self.rom[..program.len()].copy_from_slice(program);
+        self.ops = None;
+    }
+
+    /// Sets the program counter back to 0, as the CPU's reset input does.
+    pub fn reset(&mut self) {
+        self.pc = 0;
+    }
+
+    pub fn a(&self) -> u16 {
+        self.a
+    }
+
+    pub fn d(&self) -> u16 {
+        self.d
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_a(&mut self, value: u16) {
+        self.a = value;
+    }
+
+    pub fn set_d(&mut self, value: u16) {
+        self.d = value;
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn set_cycles(&mut self, cycles: u64) {
+        self.cycles = cycles;
+    }
+
+    pub fn rom(&self) -> &[u16] {
+        &self.rom
+    }
+
+    pub fn rom_mut(&mut self) -> &mut [u16] {
+        self.ops = None;
+        &mut self.rom
+    }
+
+    pub fn ram(&self) -> &[u16] {
+        &self.ram[..RAM_SIZE]
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u16] {
+        &mut self.ram[..RAM_SIZE]
+    }
+
+    /// Reads data memory, addresses past the keyboard read as 0.
+    pub fn read(&self, address: u16) -> u16 {
+        self.ram.get(address as usize).cloned().unwrap_or(0)
+    }
+
+    /// Writes data memory, addresses past the keyboard are ignored.
+    pub fn write(&mut self, address: u16, value: u16) {
+        if let Some(word) = self.ram[..RAM_SIZE].get_mut(address as usize) {
+            *word = value;
+        }
+    }
+
+    pub fn screen(&self) -> &[u16] {
+        &self.ram[SCREEN..SCREEN + SCREEN_SIZE]
+    }
+
+    pub fn key(&self) -> u16 {
+        self.ram[KBD]
+    }
+
+    /// Sets the keyboard register, 0 means no key is pressed.
+    pub fn set_key(&mut self, code: u16) {
+        self.ram[KBD] = code;
+    }
+
+    /// Executes the instruction at PC and returns the memory write it made.
+    pub fn step(&mut self) -> Option<MemoryWrite> {
+        let instruction = self.rom[(self.pc & 0x7fff) as usize];
+        self.cycles += 1;
+
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc = self.pc.wrapping_add(1) & 0x7fff;
+            return None;
+        }
+
+        // registers commit on the clock edge, so M and the jump target use the old A
+        let address = self.a & 0x7fff;
+        let y = if instruction & 0x1000 != 0 {
+            self.read(address)
+        } else {
+            self.a
+        };
+        let out = alu(self.d, y, (instruction >> 6) & 0b111111);
+
+        let mut write = None;
+        if instruction & 0b001000 != 0 {
+            // the keyboard register is read-only
+            if (address as usize) < KBD {
+                self.ram[address as usize] = out;
+            }
+            write = Some(MemoryWrite {
+                address,
+                value: out,
+            });
+        }
+        if instruction & 0b010000 != 0 {
+            self.d = out;
+        }
+        let old_a = self.a;
+        if instruction & 0b100000 != 0 {
+            self.a = out;
+        }
+
+        let negative = out & 0x8000 != 0;
+        let zero = out == 0;
+        let jump = (instruction & 0b100 != 0 && negative)
+            || (instruction & 0b010 != 0 && zero)
+            || (instruction & 0b001 != 0 && !negative && !zero);
+        self.pc = if jump {
+            old_a & 0x7fff
+        } else {
+            self.pc.wrapping_add(1) & 0x7fff
+        };
+        write
+    }
+
+    /// True if the CPU sits in the `(END) @END 0;JMP` loop that ends Hack
+    /// programs.
+    pub fn is_halted(&self) -> bool {
+        let pc = self.pc as usize;
+        // 0b1110101010000111 is `0;JMP`
+        pc + 1 < ROM_SIZE && self.rom[pc] == self.pc && self.rom[pc + 1] == 0b1110101010000111
+    }
+
+    /// Runs until the program halts or `max_cycles` instructions have been
+    /// executed and returns the number of executed instructions. Executes
+    /// the predecoded ROM, see the `decode` module.
+    pub fn run(&mut self, max_cycles: u64) -> u64 {
+        self.run_decoded(max_cycles)
+    }
+}
@@ -0,0 +1,216 @@
+use crate::program::Program;
+use crate::Computer;
+use std::collections::HashMap;
+
+/// Name of the code before the first function label, the root of all stacks.
+pub const TOP: &str = "(top)";
+
+/// Counts executed cycles per ROM address and per call stack.
+///
+/// Cycles are attributed to the enclosing function label. In VM-translated
+/// code, recognized by its `Class.function` labels, only those are function
+/// labels; in hand-written code every label that isn't a `function$label`
+/// local label is.
+///
+/// A taken jump directly followed by a label is a call site, the label its
+/// return address. Arriving at the start of a function after such a jump,
+/// possibly through shared call code, enters the function, and reaching the
+/// return address leaves it again. In hand-written code, a jump back to the
+/// start of the current function is a loop unless the return label is local.
+pub struct Profiler {
+    counts: Vec<u64>,
+    functions: Vec<String>,
+    function_of: Vec<usize>,
+    function_start: HashMap<u16, usize>,
+    /// Per ROM address: whether a label is defined there and whether one of
+    /// them is local.
+    return_label: Vec<Option<bool>>,
+    vm: bool,
+    /// Call tree nodes as (parent, function), node 0 is the root.
+    nodes: Vec<(usize, usize)>,
+    children: HashMap<(usize, usize), usize>,
+    stack: Vec<(usize, u16)>,
+    node: usize,
+    pending_return: Option<u16>,
+    /// Cycles per (call tree node, executing function).
+    samples: HashMap<(usize, usize), u64>,
+}
+
+impl Profiler {
+    pub fn new(program: &Program) -> Self {
+        let size = program.words().len();
+        let vm = program
+            .labels()
+            .iter()
+            .any(|(label, _)| label.contains('.') && !label.contains('$'));
+        let mut functions = vec![TOP.to_string()];
+        let mut function_start = HashMap::new();
+        let mut function_of = vec![0; size];
+        let mut return_label = vec![None; size + 1];
+        for (label, address) in program.labels() {
+            let address = *address as usize;
+            let local = label.contains('$');
+            if let Some(entry) = return_label.get_mut(address) {
+                *entry = Some(entry.unwrap_or(false) || local);
+            }
+            if !local && (!vm || label.contains('.')) {
+                functions.push(label.clone());
+                // of several labels at one address the last one wins
+                function_start.insert(address as u16, functions.len() - 1);
+            }
+        }
+        let mut owner = 0;
+        for (address, function) in function_of.iter_mut().enumerate() {
+            if let Some(start) = function_start.get(&(address as u16)) {
+                owner = *start;
+            }
+            *function = owner;
+        }
+        Self {
+            counts: vec![0; size],
+            functions,
+            function_of,
+            function_start,
+            return_label,
+            vm,
+            nodes: vec![(0, 0)],
+            children: HashMap::new(),
+            stack: Vec::new(),
+            node: 0,
+            pending_return: None,
+            samples: HashMap::new(),
+        }
+    }
+
+    fn function_at(&self, address: u16) -> usize {
+        self.function_of.get(address as usize).cloned().unwrap_or(0)
+    }
+
+    /// Tracks calls and returns after a taken jump from `from` to `to`.
+    fn jump(&mut self, from: u16, to: u16) {
+        if let Some((caller, return_address)) = self.stack.last() {
+            if *return_address == to {
+                self.node = *caller;
+                self.stack.pop();
+            }
+        }
+        if self.pending_return == Some(to) {
+            self.pending_return = None;
+        }
+        let next = from.wrapping_add(1);
+        if let Some(Some(_)) = self.return_label.get(next as usize) {
+            self.pending_return = Some(next);
+        }
+
+        let (function, return_address) = match (self.function_start.get(&to), self.pending_return) {
+            (Some(function), Some(return_address)) => (*function, return_address),
+            _ => return,
+        };
+        let local = self.return_label[return_address as usize] == Some(true);
+        if !self.vm && !local && function == self.function_at(from) {
+            return;
+        }
+        let next = self.nodes.len();
+        let node = *self.children.entry((self.node, function)).or_insert(next);
+        if node == next {
+            self.nodes.push((self.node, function));
+        }
+        self.stack.push((self.node, return_address));
+        self.node = node;
+        self.pending_return = None;
+    }
+
+    /// Runs like `Computer::run` while counting every executed instruction.
+    pub fn run(&mut self, computer: &mut Computer, max_cycles: u64) -> u64 {
+        let mut executed = 0;
+        while executed < max_cycles && !computer.is_halted() {
+            let from = computer.pc();
+            if let Some(count) = self.counts.get_mut(from as usize) {
+                *count += 1;
+            }
+            let key = (self.node, self.function_at(from));
+            *self.samples.entry(key).or_insert(0) += 1;
+
+            computer.step();
+            executed += 1;
+
+            let to = computer.pc();
+            if to != from.wrapping_add(1) {
+                self.jump(from, to);
+            }
+        }
+        executed
+    }
+
+    /// Executed cycles per ROM address.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Cycles per function, most expensive first.
+    pub fn flat(&self) -> Vec<(&str, u64)> {
+        let mut cycles = vec![0; self.functions.len()];
+        for (address, count) in self.counts.iter().enumerate() {
+            cycles[self.function_of[address]] += count;
+        }
+        let mut flat: Vec<(&str, u64)> = self
+            .functions
+            .iter()
+            .map(|name| name.as_str())
+            .zip(cycles)
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        flat.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        flat
+    }
+
+    /// Formats the flat profile as a table with the share of all cycles.
+    pub fn flat_report(&self) -> String {
+        let total = self.total().max(1) as f64;
+        let mut report = format!("{:>12} {:>7}  {}\n", "cycles", "%", "label");
+        for (name, count) in self.flat() {
+            report.push_str(&format!(
+                "{:>12} {:>7.2}  {}\n",
+                count,
+                count as f64 * 100.0 / total,
+                name
+            ));
+        }
+        report
+    }
+
+    fn frames(&self, mut node: usize) -> Vec<&str> {
+        let mut frames = Vec::new();
+        while node != 0 {
+            let (parent, function) = self.nodes[node];
+            frames.push(self.functions[function].as_str());
+            node = parent;
+        }
+        frames.push(TOP);
+        frames.reverse();
+        frames
+    }
+
+    /// The cycles per call stack in the collapsed format read by flamegraph
+    /// tools, one `outer;inner count` line per stack, sorted by stack.
+    pub fn collapsed(&self) -> String {
+        let mut lines: Vec<String> = self
+            .samples
+            .iter()
+            .map(|((node, function), count)| {
+                let mut frames = self.frames(*node);
+                let executing = self.functions[*function].as_str();
+                if frames.last() != Some(&executing) {
+                    frames.push(executing);
+                }
+                format!("{} {}", frames.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| line.to_string() + "\n").collect()
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{parse_hack, BoxResult};
+use assembler::{command_type, split_lines, Command, Parser, SourceLine, SymbolTable};
+use std::collections::HashMap;
+
+/// A program assembled from `.asm` source that keeps the symbol table and
+/// the source line of every ROM word.
+pub struct Program {
+    words: Vec<u16>,
+    lines: Vec<SourceLine>,
+    labels: Vec<(String, u16)>,
+    symbols: HashMap<String, u32>,
+}
+
+impl Program {
+    pub fn assemble(source: &str) -> BoxResult<Self> {
+        let mut parser = Parser::new(source);
+        let words = parse_hack(&parser.to_bytes()?)?;
+        let symbols = parser.symbol_table().symbol_map().clone();
+
+        let mut lines = Vec::new();
+        let mut labels = Vec::new();
+        for line in split_lines(source) {
+            if line.command.is_empty() {
+                continue;
+            }
+            match command_type(&line.command) {
+                Ok(Command::LCommand) => {
+                    let label = SymbolTable::parse_symbol(line.command.clone(), Command::LCommand)?;
+                    labels.push((label, lines.len() as u16));
+                }
+                _ => lines.push(line),
+            }
+        }
+        Ok(Self {
+            words,
+            lines,
+            labels,
+            symbols,
+        })
+    }
+
+    pub fn words(&self) -> &[u16] {
+        &self.words
+    }
+
+    /// The source line assembled into ROM `address`.
+    pub fn source_line(&self, address: u16) -> Option<&SourceLine> {
+        self.lines.get(address as usize)
+    }
+
+    /// All labels with their ROM addresses, in source order.
+    pub fn labels(&self) -> &[(String, u16)] {
+        &self.labels
+    }
+
+    pub fn label_address(&self, name: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(label, _)| label == name)
+            .map(|(_, address)| *address)
+    }
+
+    /// The last label defined at or before ROM `address`.
+    pub fn enclosing_label(&self, address: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .rev()
+            .find(|(_, label_address)| *label_address <= address)
+            .map(|(label, _)| label.as_str())
+    }
+
+    /// Resolves any symbol: labels to ROM, variables and predefined symbols
+    /// to RAM addresses.
+    pub fn symbol(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).map(|address| *address as u16)
+    }
+
+    pub fn symbols(&self) -> &HashMap<String, u32> {
+        &self.symbols
+    }
+}
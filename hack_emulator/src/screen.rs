@@ -0,0 +1,302 @@
+use crate::BoxResult;
+use std::fs;
+use std::path::Path;
+
+pub const SCREEN_WIDTH: usize = 512;
+pub const SCREEN_HEIGHT: usize = 256;
+/// Words per screen row, each word holds 16 pixels.
+pub const ROW_WORDS: usize = SCREEN_WIDTH / 16;
+
+/// A 1-bit image of the screen, `true` is a black pixel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+/// Result of comparing two frames pixel by pixel.
+#[derive(Debug, PartialEq)]
+pub struct FrameDiff {
+    pub pixels: usize,
+    pub first: Option<(usize, usize)>,
+}
+
+impl FrameDiff {
+    pub fn is_empty(&self) -> bool {
+        self.pixels == 0
+    }
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![false; width * height],
+        }
+    }
+
+    /// Builds a frame from the 8K words of the SCREEN memory map. Pixel
+    /// (row, col) is bit `col % 16` of word `row * 32 + col / 16`.
+    pub fn from_screen(screen: &[u16]) -> Self {
+        let mut frame = Self::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let word = screen[y * ROW_WORDS + x / 16];
+                frame.pixels[y * SCREEN_WIDTH + x] = word & (1 << (x % 16)) != 0;
+            }
+        }
+        frame
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, black: bool) {
+        self.pixels[y * self.width + x] = black;
+    }
+
+    /// Packs each row into bytes, most significant bit first, 1 for black.
+    fn packed_rows(&self) -> Vec<u8> {
+        let row_bytes = self.width.div_ceil(8);
+        let mut data = vec![0u8; row_bytes * self.height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.pixel(x, y) {
+                    data[y * row_bytes + x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        data
+    }
+
+    /// Encodes the frame as a binary (P4) PBM image.
+    pub fn to_pbm(&self) -> Vec<u8> {
+        let mut data = format!("P4\n{} {}\n", self.width, self.height).into_bytes();
+        data.extend(self.packed_rows());
+        data
+    }
+
+    /// Decodes a plain (P1) or binary (P4) PBM image.
+    pub fn from_pbm(data: &[u8]) -> BoxResult<Self> {
+        let mut position = 0;
+        let mut header = Vec::new();
+        // magic number, width and height separated by whitespace or comments
+        while header.len() < 3 {
+            while position < data.len() && data[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            if position < data.len() && data[position] == b'#' {
+                while position < data.len() && data[position] != b'\n' {
+                    position += 1;
+                }
+                continue;
+            }
+            let start = position;
+            while position < data.len() && !data[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            if start == position {
+                bail!("Truncated PBM header.");
+            }
+            header.push(String::from_utf8_lossy(&data[start..position]).to_string());
+        }
+        let width: usize = header[1].parse()?;
+        let height: usize = header[2].parse()?;
+        let mut frame = Self::new(width, height);
+
+        match header[0].as_str() {
+            "P1" => {
+                let bits: Vec<bool> = data[position..]
+                    .iter()
+                    .filter(|byte| **byte == b'0' || **byte == b'1')
+                    .map(|byte| *byte == b'1')
+                    .collect();
+                if bits.len() < width * height {
+                    bail!("PBM image has fewer pixels than {}x{}.", width, height);
+                }
+                frame.pixels.copy_from_slice(&bits[..width * height]);
+            }
+            "P4" => {
+                // a single whitespace character separates the header from the data
+                let pixels = data.get(position + 1..).unwrap_or(&[]);
+                let row_bytes = width.div_ceil(8);
+                if pixels.len() < row_bytes * height {
+                    bail!("PBM image has fewer pixels than {}x{}.", width, height);
+                }
+                for y in 0..height {
+                    for x in 0..width {
+                        let byte = pixels[y * row_bytes + x / 8];
+                        frame.set_pixel(x, y, byte & (0x80 >> (x % 8)) != 0);
+                    }
+                }
+            }
+            magic => bail!("Unsupported PBM format `{}`.", magic),
+        }
+        Ok(frame)
+    }
+
+    /// Encodes the frame as a 1-bit grayscale PNG image.
+    pub fn to_png(&self) -> BoxResult<Vec<u8>> {
+        let mut data = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut data, self.width as u32, self.height as u32);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(png::BitDepth::One);
+            let mut writer = encoder.write_header()?;
+            // in grayscale 1 is white, the opposite of the screen
+            let rows: Vec<u8> = self.packed_rows().iter().map(|byte| !byte).collect();
+            writer.write_image_data(&rows)?;
+        }
+        Ok(data)
+    }
+
+    /// Decodes a PNG image, pixels darker than mid-gray count as black.
+    pub fn from_png(data: &[u8]) -> BoxResult<Self> {
+        let mut decoder = png::Decoder::new(data);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let samples = info.color_type.samples();
+
+        let (width, height) = (info.width as usize, info.height as usize);
+        let mut frame = Self::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let offset = y * info.line_size + x * samples;
+                let luma = if samples >= 3 {
+                    let rgb = &buffer[offset..offset + 3];
+                    (rgb[0] as u32 + rgb[1] as u32 + rgb[2] as u32) / 3
+                } else {
+                    buffer[offset] as u32
+                };
+                frame.set_pixel(x, y, luma < 128);
+            }
+        }
+        Ok(frame)
+    }
+
+    /// Loads a `.pbm` or `.png` image depending on the file extension.
+    pub fn load(path: &Path) -> BoxResult<Self> {
+        let data = fs::read(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("png") => Self::from_png(&data),
+            Some("pbm") => Self::from_pbm(&data),
+            _ => bail!("Unsupported image format: {}", path.display()),
+        }
+    }
+
+    /// Saves as `.pbm` or `.png` depending on the file extension.
+    pub fn save(&self, path: &Path) -> BoxResult<()> {
+        let data = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("png") => self.to_png()?,
+            Some("pbm") => self.to_pbm(),
+            _ => bail!("Unsupported image format: {}", path.display()),
+        };
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Shrinks the frame by `scale` in both directions. A pixel is black if
+    /// any pixel of its block is, so that thin lines don't disappear.
+    pub fn scaled(&self, scale: usize) -> Frame {
+        let scale = scale.max(1);
+        let mut frame = Frame::new(self.width.div_ceil(scale), self.height.div_ceil(scale));
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.pixel(x, y) {
+                    frame.set_pixel(x / scale, y / scale, true);
+                }
+            }
+        }
+        frame
+    }
+
+    /// Pixels outside the frame are white.
+    fn pixel_or_white(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.pixel(x, y)
+    }
+
+    /// Renders the frame as lines of Unicode braille characters, each
+    /// showing 2x4 pixels with a dot for every black pixel.
+    pub fn to_braille(&self) -> Vec<String> {
+        // the dot bit of each pixel in the 2x4 cell, by row then column
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+        (0..self.height.div_ceil(4))
+            .map(|row| {
+                (0..self.width.div_ceil(2))
+                    .map(|col| {
+                        let mut bits = 0;
+                        for (dy, dots) in DOTS.iter().enumerate() {
+                            for (dx, dot) in dots.iter().enumerate() {
+                                if self.pixel_or_white(col * 2 + dx, row * 4 + dy) {
+                                    bits |= dot;
+                                }
+                            }
+                        }
+                        std::char::from_u32(0x2800 + bits).unwrap()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Renders the frame as lines of half-block characters, each showing
+    /// two pixels on top of each other.
+    pub fn to_half_blocks(&self) -> Vec<String> {
+        (0..self.height.div_ceil(2))
+            .map(|row| {
+                (0..self.width)
+                    .map(|x| {
+                        match (
+                            self.pixel_or_white(x, row * 2),
+                            self.pixel_or_white(x, row * 2 + 1),
+                        ) {
+                            (false, false) => ' ',
+                            (true, false) => '\u{2580}',
+                            (false, true) => '\u{2584}',
+                            (true, true) => '\u{2588}',
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Counts the differing pixels and finds the first one in row order.
+    pub fn diff(&self, other: &Frame) -> BoxResult<FrameDiff> {
+        if self.width != other.width || self.height != other.height {
+            bail!(
+                "Image sizes differ: {}x{} and {}x{}.",
+                self.width,
+                self.height,
+                other.width,
+                other.height
+            );
+        }
+        let mut diff = FrameDiff {
+            pixels: 0,
+            first: None,
+        };
+        for (i, (a, b)) in self.pixels.iter().zip(other.pixels.iter()).enumerate() {
+            if a != b {
+                diff.pixels += 1;
+                if diff.first.is_none() {
+                    diff.first = Some((i % self.width, i / self.width));
+                }
+            }
+        }
+        Ok(diff)
+    }
+}
@@ -0,0 +1,544 @@
+use crate::compare::{compare, Mismatch};
+use crate::program::Program;
+use crate::{read_hack, BoxResult, Computer};
+use std::fs;
+use std::path::Path;
+
+/// A value a script can set, test and output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variable {
+    Ram(u16),
+    A,
+    D,
+    Pc,
+    /// The number of executed instructions.
+    Time,
+}
+
+impl Variable {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "A" => Some(Variable::A),
+            "D" => Some(Variable::D),
+            "PC" => Some(Variable::Pc),
+            "time" => Some(Variable::Time),
+            _ => {
+                let address = name.strip_prefix("RAM[")?.strip_suffix(']')?;
+                address.parse().ok().map(Variable::Ram)
+            }
+        }
+    }
+
+    /// Registers and memory are read as signed words.
+    fn get(self, computer: &Computer) -> i64 {
+        let word = match self {
+            Variable::Ram(address) => computer.read(address),
+            Variable::A => computer.a(),
+            Variable::D => computer.d(),
+            Variable::Pc => computer.pc(),
+            Variable::Time => return computer.cycles() as i64,
+        };
+        word as i16 as i64
+    }
+
+    fn set(self, computer: &mut Computer, value: u16) {
+        match self {
+            Variable::Ram(address) => computer.write(address, value),
+            Variable::A => computer.set_a(value),
+            Variable::D => computer.set_d(value),
+            Variable::Pc => computer.set_pc(value & 0x7fff),
+            Variable::Time => computer.set_cycles(value as u64),
+        }
+    }
+}
+
+/// An `output-list` entry such as `RAM[0]%D2.6.2`: the value printed in
+/// decimal, binary, hex or as a string, `len` characters wide with `left`
+/// and `right` spaces of padding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub variable: Variable,
+    pub radix: char,
+    pub left: usize,
+    pub len: usize,
+    pub right: usize,
+}
+
+impl Column {
+    fn parse(entry: &str) -> Option<Self> {
+        let (name, format) = entry.split_once('%')?;
+        let variable = Variable::parse(name)?;
+        let mut chars = format.chars();
+        let radix = chars.next().filter(|radix| "BDXS".contains(*radix))?;
+        let sizes: Vec<usize> = chars
+            .as_str()
+            .split('.')
+            .map(|size| size.parse().ok())
+            .collect::<Option<_>>()?;
+        let (left, len, right) = match sizes.as_slice() {
+            [left, len, right] => (*left, *len, *right),
+            _ => return None,
+        };
+        Some(Self {
+            name: name.to_string(),
+            variable,
+            radix,
+            left,
+            len,
+            right,
+        })
+    }
+
+    fn width(&self) -> usize {
+        self.left + self.len + self.right
+    }
+
+    /// The name centered in the column, cut off if it doesn't fit.
+    fn header(&self) -> String {
+        let name: String = self.name.chars().take(self.width()).collect();
+        let left = (self.width() - name.len()) / 2;
+        let right = self.width() - name.len() - left;
+        format!("{}{}{}", " ".repeat(left), name, " ".repeat(right))
+    }
+
+    fn value(&self, value: i64) -> String {
+        let len = self.len;
+        let digits = match self.radix {
+            'B' => format!("{:016b}", value as u16),
+            'X' => format!("{:04X}", value as u16),
+            'S' => format!("{:<len$}", value, len = len),
+            _ => format!("{:>len$}", value, len = len),
+        };
+        // binary and hex show the low digits only
+        let digits = if self.radix == 'B' || self.radix == 'X' {
+            let skip = digits.len().saturating_sub(len);
+            format!("{:0>len$}", &digits[skip..], len = len)
+        } else {
+            digits
+        };
+        format!(
+            "{}{}{}",
+            " ".repeat(self.left),
+            digits,
+            " ".repeat(self.right)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Load(String),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(Variable, u16),
+    Ticktock,
+    Output,
+    Echo(String),
+    Repeat(u64, Vec<Command>),
+    While(Variable, Comparison, i64, Vec<Command>),
+}
+
+#[derive(Debug)]
+struct Token {
+    text: String,
+    line: usize,
+    quoted: bool,
+}
+
+fn tokenize(source: &str) -> BoxResult<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            i += 2;
+        } else if c == '"' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && chars[i] != '"' && chars[i] != '\n' {
+                i += 1;
+            }
+            if chars.get(i) != Some(&'"') {
+                bail!("line {}: unterminated string", line);
+            }
+            let text = chars[start..i].iter().collect();
+            tokens.push(Token {
+                text,
+                line,
+                quoted: true,
+            });
+            i += 1;
+        } else if ",;!{}".contains(c) {
+            tokens.push(Token {
+                text: c.to_string(),
+                line,
+                quoted: false,
+            });
+            i += 1;
+        } else {
+            let start = i;
+            while i < chars.len() && !chars[i].is_whitespace() && !",;!{}\"".contains(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token {
+                text: chars[start..i].iter().collect(),
+                line,
+                quoted: false,
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses a number to set, decimal or with a `%D`, `%X` or `%B` prefix.
+fn parse_value(text: &str) -> Option<i64> {
+    let value = match text.get(..2) {
+        Some("%D") => text[2..].parse().ok()?,
+        Some("%X") => i64::from_str_radix(&text[2..], 16).ok()?,
+        Some("%B") => i64::from_str_radix(&text[2..], 2).ok()?,
+        _ => text.parse().ok()?,
+    };
+    if (-32768..=65535).contains(&value) {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+struct ScriptParser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl ScriptParser {
+    fn line(&self) -> usize {
+        match self.tokens.get(self.position) {
+            Some(token) => token.line,
+            None => self.tokens.last().map(|token| token.line).unwrap_or(1),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens
+            .get(self.position)
+            .map(|token| token.text.as_str())
+    }
+
+    fn next(&mut self, what: &str) -> BoxResult<String> {
+        match self.tokens.get(self.position) {
+            Some(token) if token.quoted || !",;!{}".contains(token.text.as_str()) => {
+                self.position += 1;
+                Ok(token.text.clone())
+            }
+            _ => bail!("line {}: expected {}", self.line(), what),
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> BoxResult<()> {
+        if self.peek() != Some(text) {
+            bail!("line {}: expected `{}`", self.line(), text);
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn variable(&mut self) -> BoxResult<Variable> {
+        let name = self.next("a variable")?;
+        match Variable::parse(&name) {
+            Some(variable) => Ok(variable),
+            None => bail!("line {}: unknown variable `{}`", self.line(), name),
+        }
+    }
+
+    fn value(&mut self) -> BoxResult<i64> {
+        let text = self.next("a value")?;
+        match parse_value(&text) {
+            Some(value) => Ok(value),
+            None => bail!("line {}: invalid value `{}`", self.line(), text),
+        }
+    }
+
+    /// Parses commands up to the end of the script or a closing brace.
+    fn block(&mut self, nested: bool) -> BoxResult<Vec<Command>> {
+        let mut commands = Vec::new();
+        while let Some(token) = self.peek() {
+            match token {
+                "}" if nested => return Ok(commands),
+                "," | ";" | "!" => {
+                    self.position += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            let line = self.line();
+            let name = self.next("a command")?;
+            let command = match name.as_str() {
+                "load" => Command::Load(self.next("a program file")?),
+                "output-file" => Command::OutputFile(self.next("a file name")?),
+                "compare-to" => Command::CompareTo(self.next("a file name")?),
+                "output-list" => {
+                    let mut columns = Vec::new();
+                    while let Some(entry) = self.peek() {
+                        if ",;!{}".contains(entry) {
+                            break;
+                        }
+                        match Column::parse(entry) {
+                            Some(column) => columns.push(column),
+                            None => bail!("line {}: invalid output entry `{}`", self.line(), entry),
+                        }
+                        self.position += 1;
+                    }
+                    Command::OutputList(columns)
+                }
+                "set" => {
+                    let variable = self.variable()?;
+                    Command::Set(variable, self.value()? as u16)
+                }
+                "ticktock" => Command::Ticktock,
+                "output" => Command::Output,
+                "echo" => Command::Echo(self.next("a message")?),
+                "clear-echo" => continue,
+                "repeat" => {
+                    // without a count it repeats until the script is stopped
+                    let count = if self.peek() == Some("{") {
+                        u64::MAX
+                    } else {
+                        let count = self.next("a count")?;
+                        match count.parse() {
+                            Ok(count) => count,
+                            Err(_) => bail!("line {}: invalid count `{}`", line, count),
+                        }
+                    };
+                    self.expect("{")?;
+                    let body = self.block(true)?;
+                    self.expect("}")?;
+                    Command::Repeat(count, body)
+                }
+                "while" => {
+                    let variable = self.variable()?;
+                    let comparison = match self.next("a comparison")?.as_str() {
+                        "=" => Comparison::Equal,
+                        "<>" => Comparison::NotEqual,
+                        "<" => Comparison::Less,
+                        ">" => Comparison::Greater,
+                        "<=" => Comparison::LessEqual,
+                        ">=" => Comparison::GreaterEqual,
+                        other => bail!("line {}: unknown comparison `{}`", line, other),
+                    };
+                    let value = self.value()?;
+                    self.expect("{")?;
+                    let body = self.block(true)?;
+                    self.expect("}")?;
+                    Command::While(variable, comparison, value, body)
+                }
+                other => bail!("line {}: unknown command `{}`", line, other),
+            };
+            commands.push(command);
+        }
+        if nested {
+            bail!("line {}: expected `}}`", self.line());
+        }
+        Ok(commands)
+    }
+}
+
+/// The result of running a script: the output table and whether it matched
+/// the compare file.
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub output: String,
+    pub output_file: Option<String>,
+    pub compare_to: Option<String>,
+    /// The first cell that differs from the compare file.
+    pub mismatch: Option<Mismatch>,
+}
+
+/// The state of a running script.
+struct Run<'a> {
+    dir: &'a Path,
+    computer: Option<Computer>,
+    columns: Vec<Column>,
+    outcome: Outcome,
+}
+
+impl<'a> Run<'a> {
+    fn computer(&mut self) -> BoxResult<&mut Computer> {
+        match self.computer.as_mut() {
+            Some(computer) => Ok(computer),
+            None => bail!("No program loaded."),
+        }
+    }
+
+    fn load(&mut self, filename: &str) -> BoxResult<()> {
+        let path = self.dir.join(filename);
+        let words = if filename.ends_with(".hack") {
+            read_hack(&path.to_string_lossy())?
+        } else {
+            Program::assemble(&fs::read_to_string(&path)?)?
+                .words()
+                .to_vec()
+        };
+        self.computer = Some(Computer::from_program(&words));
+        Ok(())
+    }
+
+    /// Runs `count` instructions. A halted program keeps alternating between
+    /// its two loop instructions, so those are stepped while the rest of the
+    /// count is only added to the time.
+    fn ticktock(&mut self, count: u64) -> BoxResult<()> {
+        let computer = self.computer()?;
+        let remaining = count - computer.run(count);
+        let steps = if remaining > 2 {
+            2 + remaining % 2
+        } else {
+            remaining
+        };
+        for _ in 0..steps {
+            computer.step();
+        }
+        // an unbounded `repeat` leaves a remaining count near u64::MAX
+        computer.set_cycles(computer.cycles().saturating_add(remaining - steps));
+        Ok(())
+    }
+
+    fn output(&mut self) -> BoxResult<()> {
+        let computer = match &self.computer {
+            Some(computer) => computer,
+            None => bail!("No program loaded."),
+        };
+        let mut line = String::from("|");
+        for column in &self.columns {
+            line.push_str(&column.value(column.variable.get(computer)));
+            line.push('|');
+        }
+        self.outcome.output.push_str(&line);
+        self.outcome.output.push('\n');
+        Ok(())
+    }
+
+    fn execute(&mut self, commands: &[Command]) -> BoxResult<()> {
+        for command in commands {
+            match command {
+                Command::Load(filename) => self.load(filename)?,
+                Command::OutputFile(filename) => self.outcome.output_file = Some(filename.clone()),
+                Command::CompareTo(filename) => self.outcome.compare_to = Some(filename.clone()),
+                Command::OutputList(columns) => {
+                    self.columns = columns.clone();
+                    let mut line = String::from("|");
+                    for column in columns {
+                        line.push_str(&column.header());
+                        line.push('|');
+                    }
+                    self.outcome.output.push_str(&line);
+                    self.outcome.output.push('\n');
+                }
+                Command::Set(variable, value) => variable.set(self.computer()?, *value),
+                Command::Ticktock => self.ticktock(1)?,
+                Command::Output => self.output()?,
+                Command::Echo(message) => println!("{}", message),
+                Command::Repeat(count, body) => {
+                    if body.as_slice() == [Command::Ticktock] {
+                        self.ticktock(*count)?;
+                    } else {
+                        for _ in 0..*count {
+                            self.execute(body)?;
+                        }
+                    }
+                }
+                Command::While(variable, comparison, value, body) => loop {
+                    let current = variable.get(self.computer()?);
+                    let holds = match comparison {
+                        Comparison::Equal => current == *value,
+                        Comparison::NotEqual => current != *value,
+                        Comparison::Less => current < *value,
+                        Comparison::Greater => current > *value,
+                        Comparison::LessEqual => current <= *value,
+                        Comparison::GreaterEqual => current >= *value,
+                    };
+                    if !holds {
+                        break;
+                    }
+                    self.execute(body)?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A test script in the language of the course's CPU emulator, see
+/// projects/04/mult/Mult.tst.
+#[derive(Debug, PartialEq)]
+pub struct TestScript {
+    commands: Vec<Command>,
+}
+
+impl TestScript {
+    pub fn parse(source: &str) -> BoxResult<Self> {
+        let mut parser = ScriptParser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        Ok(Self {
+            commands: parser.block(false)?,
+        })
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    /// Runs the script with file names resolved relative to `dir`, and
+    /// compares the output to the `compare-to` file if there is one.
+    pub fn run(&self, dir: &Path) -> BoxResult<Outcome> {
+        let mut run = Run {
+            dir,
+            computer: None,
+            columns: Vec::new(),
+            outcome: Outcome {
+                output: String::new(),
+                output_file: None,
+                compare_to: None,
+                mismatch: None,
+            },
+        };
+        run.execute(&self.commands)?;
+        let mut outcome = run.outcome;
+        if let Some(filename) = &outcome.compare_to {
+            let expected = fs::read_to_string(dir.join(filename))?;
+            outcome.mismatch = compare(&outcome.output, &expected).into_iter().next();
+        }
+        Ok(outcome)
+    }
+}
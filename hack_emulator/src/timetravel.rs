@@ -0,0 +1,273 @@
+use crate::debugger::Stop;
+use crate::{BoxResult, Computer, MemoryWrite, RAM_SIZE, ROM_SIZE};
+use std::collections::{BTreeSet, VecDeque};
+use std::fs;
+use std::path::Path;
+
+/// Instructions kept in the history unless `set_limit` says otherwise.
+pub const DEFAULT_LIMIT: usize = 1_000_000;
+
+/// What an instruction overwrote, enough to undo it.
+#[derive(Debug, Clone, Copy)]
+struct Undo {
+    a: u16,
+    d: u16,
+    pc: u16,
+    /// The written address and its previous value.
+    write: Option<(u16, u16)>,
+}
+
+/// Runs a computer while logging every register and RAM write, so that
+/// execution can be stepped backwards. Changes made through `computer_mut`,
+/// such as key presses, are not logged.
+pub struct Recorder {
+    computer: Computer,
+    log: VecDeque<Undo>,
+    limit: usize,
+    watchpoints: BTreeSet<u16>,
+}
+
+impl Recorder {
+    pub fn new(computer: Computer) -> Self {
+        Self {
+            computer,
+            log: VecDeque::new(),
+            limit: DEFAULT_LIMIT,
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn computer(&self) -> &Computer {
+        &self.computer
+    }
+
+    pub fn computer_mut(&mut self) -> &mut Computer {
+        &mut self.computer
+    }
+
+    /// Keeps at most `limit` instructions, forgetting the oldest ones.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        while self.log.len() > limit {
+            self.log.pop_front();
+        }
+    }
+
+    /// The number of instructions that can be stepped back.
+    pub fn history(&self) -> usize {
+        self.log.len()
+    }
+
+    pub fn clear_history(&mut self) {
+        self.log.clear();
+    }
+
+    pub fn watchpoints(&self) -> &BTreeSet<u16> {
+        &self.watchpoints
+    }
+
+    /// Watches writes to a RAM address. Returns false if it was watched
+    /// already.
+    pub fn add_watchpoint(&mut self, address: u16) -> bool {
+        self.watchpoints.insert(address)
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) -> bool {
+        self.watchpoints.remove(&address)
+    }
+
+    /// Executes one instruction, reporting a write to a watched address.
+    pub fn step(&mut self) -> Stop {
+        if self.computer.is_halted() {
+            return Stop::Halted;
+        }
+        let computer = &mut self.computer;
+        let mut undo = Undo {
+            a: computer.a(),
+            d: computer.d(),
+            pc: computer.pc(),
+            write: None,
+        };
+        // only the old value at A can be overwritten
+        let old = computer.read(computer.a() & 0x7fff);
+        let write = computer.step();
+        if let Some(MemoryWrite { address, .. }) = write {
+            undo.write = Some((address, old));
+        }
+        if self.limit > 0 {
+            if self.log.len() == self.limit {
+                self.log.pop_front();
+            }
+            self.log.push_back(undo);
+        }
+        match write {
+            Some(write) if self.watchpoints.contains(&write.address) => {
+                Stop::Watchpoint(write.address)
+            }
+            _ => Stop::Done,
+        }
+    }
+
+    /// Runs until a watched address is written or the program halts.
+    pub fn resume(&mut self, max_cycles: u64) -> Stop {
+        for _ in 0..max_cycles {
+            match self.step() {
+                Stop::Done => {}
+                stop => return stop,
+            }
+        }
+        Stop::Limit
+    }
+
+    /// Undoes the last instruction. Reports if it had written a watched
+    /// address, the state is then the one right before that write.
+    pub fn reverse_step(&mut self) -> Stop {
+        let undo = match self.log.pop_back() {
+            Some(undo) => undo,
+            None => return Stop::HistoryStart,
+        };
+        let computer = &mut self.computer;
+        computer.set_a(undo.a);
+        computer.set_d(undo.d);
+        computer.set_pc(undo.pc);
+        computer.set_cycles(computer.cycles() - 1);
+        match undo.write {
+            Some((address, old)) => {
+                computer.write(address, old);
+                if self.watchpoints.contains(&address) {
+                    Stop::Watchpoint(address)
+                } else {
+                    Stop::Done
+                }
+            }
+            None => Stop::Done,
+        }
+    }
+
+    /// Steps backwards to the last write of a watched address, stopping at
+    /// the instruction that made it.
+    pub fn reverse_resume(&mut self) -> Stop {
+        loop {
+            match self.reverse_step() {
+                Stop::Done => {}
+                stop => return stop,
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::of(&self.computer)
+    }
+
+    /// Replaces the whole machine state. The history is cleared as it led
+    /// to a different state.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        snapshot.apply(&mut self.computer);
+        self.log.clear();
+    }
+}
+
+/// The complete state of the machine, which can be saved to disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    pub cycles: u64,
+    pub ram: Vec<u16>,
+    pub rom: Vec<u16>,
+}
+
+impl Snapshot {
+    pub fn of(computer: &Computer) -> Self {
+        Self {
+            a: computer.a(),
+            d: computer.d(),
+            pc: computer.pc(),
+            cycles: computer.cycles(),
+            ram: computer.ram().to_vec(),
+            rom: computer.rom().to_vec(),
+        }
+    }
+
+    pub fn apply(&self, computer: &mut Computer) {
+        computer.load_rom(&self.rom);
+        computer.ram_mut().copy_from_slice(&self.ram);
+        computer.set_a(self.a);
+        computer.set_d(self.d);
+        computer.set_pc(self.pc);
+        computer.set_cycles(self.cycles);
+    }
+
+    /// Writes the registers followed by one `ram ADDRESS VALUE` or
+    /// `rom ADDRESS VALUE` line for every word that isn't 0.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "hack-snapshot 1\na {}\nd {}\npc {}\ncycles {}\n",
+            self.a, self.d, self.pc, self.cycles
+        );
+        for (name, memory) in [("ram", &self.ram), ("rom", &self.rom)].iter() {
+            for (address, value) in memory.iter().enumerate() {
+                if *value != 0 {
+                    text.push_str(&format!("{} {} {}\n", name, address, value));
+                }
+            }
+        }
+        text
+    }
+
+    pub fn parse(content: &str) -> BoxResult<Self> {
+        let mut lines = content.lines();
+        if lines.next().map(|line| line.trim()) != Some("hack-snapshot 1") {
+            bail!("Not a Hack snapshot.");
+        }
+        let mut snapshot = Snapshot {
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+            ram: vec![0; RAM_SIZE],
+            rom: vec![0; ROM_SIZE],
+        };
+        for (i, line) in lines.enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let number = |field: usize, max: u64| -> BoxResult<u64> {
+                match fields.get(field).map(|value| value.parse::<u64>()) {
+                    Some(Ok(value)) if value <= max => Ok(value),
+                    _ => bail!("line {}: invalid number in `{}`", i + 2, line),
+                }
+            };
+            let word = |field: usize| number(field, 0xffff).map(|value| value as u16);
+            match fields.first() {
+                None => continue,
+                Some(&"a") => snapshot.a = word(1)?,
+                Some(&"d") => snapshot.d = word(1)?,
+                Some(&"pc") => snapshot.pc = word(1)? & 0x7fff,
+                Some(&"cycles") => snapshot.cycles = number(1, u64::MAX)?,
+                Some(&name) if name == "ram" || name == "rom" => {
+                    let memory = if name == "ram" {
+                        &mut snapshot.ram
+                    } else {
+                        &mut snapshot.rom
+                    };
+                    let address = word(1)? as usize;
+                    match memory.get_mut(address) {
+                        Some(value) => *value = word(2)?,
+                        None => bail!("line {}: {} address {} out of range", i + 2, name, address),
+                    }
+                }
+                Some(other) => bail!("line {}: unknown field `{}`", i + 2, other),
+            }
+        }
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: &Path) -> BoxResult<()> {
+        fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> BoxResult<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
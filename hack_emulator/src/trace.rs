@@ -0,0 +1,338 @@
+use crate::{BoxResult, Computer, MemoryWrite};
+use assembler::Code;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+/// Starts a binary trace, followed by 12 bytes per record.
+const MAGIC: &[u8] = b"HACKTRC1";
+/// Marks a binary record without a write, as writes go below KBD.
+const NO_WRITE: u16 = 0xffff;
+const CSV_HEADER: &str = "cycle,pc,word,instruction,a,d,address,value";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Binary,
+}
+
+/// One executed instruction: where it was, what it was, the registers after
+/// it and the memory it wrote.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Record {
+    pub cycle: u64,
+    pub pc: u16,
+    pub instruction: u16,
+    pub a: u16,
+    pub d: u16,
+    pub write: Option<MemoryWrite>,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mnemonic = Code::new()
+            .decode(&format!("{:016b}", self.instruction))
+            .unwrap_or_else(|_| String::from("???"));
+        write!(
+            f,
+            "cycle {} pc {} {} A={} D={}",
+            self.cycle, self.pc, mnemonic, self.a, self.d
+        )?;
+        if let Some(write) = self.write {
+            write!(f, " RAM[{}]={}", write.address, write.value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes trace records, decoding each distinct instruction once.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: Format,
+    code: Code<'static>,
+    mnemonics: HashMap<u16, String>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut out: W, format: Format) -> BoxResult<Self> {
+        match format {
+            Format::Csv => writeln!(out, "{}", CSV_HEADER)?,
+            Format::Binary => out.write_all(MAGIC)?,
+        }
+        Ok(Self {
+            out,
+            format,
+            code: Code::new(),
+            mnemonics: HashMap::new(),
+        })
+    }
+
+    pub fn write(&mut self, record: &Record) -> BoxResult<()> {
+        match self.format {
+            Format::Csv => {
+                let code = &self.code;
+                let mnemonic = self.mnemonics.entry(record.instruction).or_insert_with(|| {
+                    code.decode(&format!("{:016b}", record.instruction))
+                        .unwrap_or_else(|_| String::from("???"))
+                });
+                let (address, value) = match record.write {
+                    Some(write) => (write.address.to_string(), write.value.to_string()),
+                    None => (String::new(), String::new()),
+                };
+                writeln!(
+                    self.out,
+                    "{},{},{:016b},{},{},{},{},{}",
+                    record.cycle,
+                    record.pc,
+                    record.instruction,
+                    mnemonic,
+                    record.a,
+                    record.d,
+                    address,
+                    value
+                )?;
+            }
+            Format::Binary => {
+                let write = record.write.unwrap_or(MemoryWrite {
+                    address: NO_WRITE,
+                    value: 0,
+                });
+                let words = [
+                    record.pc,
+                    record.instruction,
+                    record.a,
+                    record.d,
+                    write.address,
+                    write.value,
+                ];
+                for word in words.iter() {
+                    self.out.write_all(&word.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Runs the computer for up to `max_cycles` instructions or until it halts,
+/// recording every instruction. Returns the number of executed instructions.
+pub fn trace<W: Write>(
+    computer: &mut Computer,
+    max_cycles: u64,
+    writer: &mut TraceWriter<W>,
+) -> BoxResult<u64> {
+    let mut executed = 0;
+    while executed < max_cycles && !computer.is_halted() {
+        let pc = computer.pc();
+        let instruction = computer.rom()[pc as usize];
+        let write = computer.step();
+        writer.write(&Record {
+            cycle: computer.cycles() - 1,
+            pc,
+            instruction,
+            a: computer.a(),
+            d: computer.d(),
+            write,
+        })?;
+        executed += 1;
+    }
+    writer.out.flush()?;
+    Ok(executed)
+}
+
+/// Reads the records of a trace in either format, recognized by its start.
+pub struct TraceReader<R: BufRead> {
+    input: R,
+    format: Format,
+    /// Records read so far, the cycle of binary records.
+    count: u64,
+    line: usize,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(mut input: R) -> BoxResult<Self> {
+        let format = if input.fill_buf()?.starts_with(MAGIC) {
+            input.consume(MAGIC.len());
+            Format::Binary
+        } else {
+            let mut header = String::new();
+            input.read_line(&mut header)?;
+            if header.trim() != CSV_HEADER {
+                bail!("Not a trace, expected `{}` or a binary trace.", CSV_HEADER);
+            }
+            Format::Csv
+        };
+        Ok(Self {
+            input,
+            format,
+            count: 0,
+            line: 1,
+        })
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    fn read_binary(&mut self) -> BoxResult<Option<Record>> {
+        let mut bytes = [0u8; 12];
+        let mut filled = 0;
+        while filled < bytes.len() {
+            let read = self.input.read(&mut bytes[filled..])?;
+            if read == 0 {
+                if filled == 0 {
+                    return Ok(None);
+                }
+                bail!("record {}: truncated", self.count);
+            }
+            filled += read;
+        }
+        let word = |i: usize| u16::from_le_bytes([bytes[2 * i], bytes[2 * i + 1]]);
+        let write = match word(4) {
+            NO_WRITE => None,
+            address => Some(MemoryWrite {
+                address,
+                value: word(5),
+            }),
+        };
+        let record = Record {
+            cycle: self.count,
+            pc: word(0),
+            instruction: word(1),
+            a: word(2),
+            d: word(3),
+            write,
+        };
+        self.count += 1;
+        Ok(Some(record))
+    }
+
+    fn read_csv(&mut self) -> BoxResult<Option<Record>> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        let fields: Vec<&str> = line.trim_end().split(',').collect();
+        if fields.len() != 8 {
+            bail!(
+                "line {}: expected 8 fields, found {}",
+                self.line,
+                fields.len()
+            );
+        }
+        let number = |field: usize| -> BoxResult<u64> {
+            match fields[field].parse() {
+                Ok(value) => Ok(value),
+                Err(_) => bail!("line {}: invalid number `{}`", self.line, fields[field]),
+            }
+        };
+        let word = |field: usize| -> BoxResult<u16> {
+            match number(field)? {
+                value if value <= 0xffff => Ok(value as u16),
+                value => bail!("line {}: {} doesn't fit in a word", self.line, value),
+            }
+        };
+        let instruction = match u16::from_str_radix(fields[2], 2) {
+            Ok(word) => word,
+            Err(_) => bail!(
+                "line {}: invalid instruction word `{}`",
+                self.line,
+                fields[2]
+            ),
+        };
+        let write = if fields[6].is_empty() {
+            None
+        } else {
+            Some(MemoryWrite {
+                address: word(6)?,
+                value: word(7)?,
+            })
+        };
+        Ok(Some(Record {
+            cycle: number(0)?,
+            pc: word(1)?,
+            instruction,
+            a: word(4)?,
+            d: word(5)?,
+            write,
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = BoxResult<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.format {
+            Format::Binary => self.read_binary(),
+            Format::Csv => self.read_csv(),
+        };
+        record.transpose()
+    }
+}
+
+/// Where two traces stop agreeing. A missing record means that trace ended.
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    /// The position of the records in the compared sequences.
+    pub index: u64,
+    pub left: Option<Record>,
+    pub right: Option<Record>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |record: &Option<Record>| match record {
+            Some(record) => record.to_string(),
+            None => String::from("<end of trace>"),
+        };
+        writeln!(f, "Traces diverge at record {}", self.index)?;
+        writeln!(f, "  left:  {}", describe(&self.left))?;
+        write!(f, "  right: {}", describe(&self.right))
+    }
+}
+
+/// Compares two traces record by record, cycle numbers aside, and stops at
+/// the first difference. With `writes_only` just the sequence of memory
+/// writes is compared, so that programs taking different instructions to
+/// the same effect agree.
+pub fn diff(
+    left: impl Iterator<Item = BoxResult<Record>>,
+    right: impl Iterator<Item = BoxResult<Record>>,
+    writes_only: bool,
+) -> BoxResult<Option<Divergence>> {
+    let keep = |record: &BoxResult<Record>| match record {
+        Ok(record) => !writes_only || record.write.is_some(),
+        Err(_) => true,
+    };
+    let mut left = left.filter(keep);
+    let mut right = right.filter(keep);
+    let mut index = 0;
+    loop {
+        let (l, r) = (left.next().transpose()?, right.next().transpose()?);
+        let same = match (&l, &r) {
+            (None, None) => return Ok(None),
+            (Some(l), Some(r)) if writes_only => l.write == r.write,
+            (Some(l), Some(r)) => {
+                Record {
+                    cycle: r.cycle,
+                    ..*l
+                } == *r
+            }
+            _ => false,
+        };
+        if !same {
+            return Ok(Some(Divergence {
+                index,
+                left: l,
+                right: r,
+            }));
+        }
+        index += 1;
+    }
+}
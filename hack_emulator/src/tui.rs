@@ -0,0 +1,50 @@
+//! Terminal setup shared by the full screen tools.
+use std::io;
+
+use crossterm::cursor::{Hide, Show};
+use crossterm::event::{
+    KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::execute;
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+
+/// Raw mode on a cleared alternate screen with the cursor hidden. The
+/// terminal is restored when this is dropped, also if drawing failed.
+pub struct Terminal {
+    reports_release: bool,
+}
+
+impl Terminal {
+    /// Takes over the terminal, asking it to report key releases if
+    /// `reports_release` is set, which needs keyboard enhancement support.
+    pub fn enter(reports_release: bool) -> io::Result<Terminal> {
+        terminal::enable_raw_mode()?;
+        let mut guard = Terminal {
+            reports_release: false,
+        };
+        execute!(
+            io::stdout(),
+            EnterAlternateScreen,
+            Hide,
+            Clear(ClearType::All)
+        )?;
+        if reports_release {
+            execute!(
+                io::stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+            guard.reports_release = true;
+        }
+        Ok(guard)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.reports_release {
+            let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
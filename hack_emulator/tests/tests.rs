@@ -0,0 +1,1013 @@
+use assembler::Parser;
+use hack_emulator::compare::{compare, Mismatch};
+use hack_emulator::debugger::{Debugger, Stop};
+use hack_emulator::decode::{decode, Alu, Op};
+use hack_emulator::gdb::{self, GdbStub, Response};
+use hack_emulator::keyboard::{self, key_code, KeyEvent, KeyScript};
+use hack_emulator::profiler::Profiler;
+use hack_emulator::program::Program;
+use hack_emulator::screen::{Frame, SCREEN_HEIGHT, SCREEN_WIDTH};
+use hack_emulator::testscript::{Command, TestScript};
+use hack_emulator::timetravel::{Recorder, Snapshot};
+use hack_emulator::trace::{self, Format, Record, TraceReader, TraceWriter};
+use hack_emulator::{alu, parse_hack, Computer, MemoryWrite, KBD, SCREEN};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+
+const MULT: &str = include_str!("../../projects/04/mult/Mult.asm");
+const FILL: &str = include_str!("../../projects/04/fill/Fill.asm");
+const RECT: &str = include_str!("../../projects/06/rect/Rect.asm");
+const PONG: &str = include_str!("../../projects/06/pong/Pong.asm");
+// doubles R0 twice by calling DOUBLE with the return address in R15
+const CALLS: &str = "
+    @3
+    D=A
+    @R0
+    M=D
+    @RET1
+    D=A
+    @R15
+    M=D
+    @DOUBLE
+    0;JMP
+(RET1)
+    @RET2
+    D=A
+    @R15
+    M=D
+    @DOUBLE
+    0;JMP
+(RET2)
+(END)
+    @END
+    0;JMP
+(DOUBLE)
+    @R0
+    D=M
+    M=D+M
+    @R15
+    A=M
+    0;JMP
+";
+// counts R1 down to 0 recursively, return addresses are kept on the stack
+const RECURSION: &str = "
+    @256
+    D=A
+    @SP
+    M=D
+    @3
+    D=A
+    @R1
+    M=D
+    @Main.main$ret0
+    D=A
+    @Rec.down
+    0;JMP
+(Main.main$ret0)
+(END)
+    @END
+    0;JMP
+(Rec.down)
+    @SP
+    AM=M+1
+    A=A-1
+    M=D
+    @R1
+    MD=M-1
+    @Rec.down$base
+    D;JEQ
+    @Rec.down$ret0
+    D=A
+    @Rec.down
+    0;JMP
+(Rec.down$ret0)
+(Rec.down$base)
+    @SP
+    AM=M-1
+    A=M
+    0;JMP
+";
+
+fn assemble(source: &str) -> Computer {
+    let mut parser = Parser::new(source);
+    Computer::from_hack(&parser.to_bytes().unwrap()).unwrap()
+}
+
+#[test]
+fn test_alu() {
+    let (x, y) = (5u16, 3u16);
+    // control bits and expected result for each of the 18 ALU functions
+    let table: [(u16, u16); 18] = [
+        (0b101010, 0),
+        (0b111111, 1),
+        (0b111010, 0xffff),
+        (0b001100, x),
+        (0b110000, y),
+        (0b001101, !x),
+        (0b110001, !y),
+        (0b001111, x.wrapping_neg()),
+        (0b110011, y.wrapping_neg()),
+        (0b011111, x + 1),
+        (0b110111, y + 1),
+        (0b001110, x - 1),
+        (0b110010, y - 1),
+        (0b000010, x + y),
+        (0b010011, x - y),
+        (0b000111, y.wrapping_sub(x)),
+        (0b000000, x & y),
+        (0b010101, x | y),
+    ];
+    for (control, expected) in table.iter() {
+        assert_eq!(*expected, alu(x, y, *control), "control {:06b}", control);
+    }
+}
+
+#[test]
+fn test_parse_hack() {
+    assert_eq!(
+        vec![2, 0xec10],
+        parse_hack("0000000000000010\n1110110000010000\n\n").unwrap()
+    );
+    assert!(parse_hack("0000000000000010\n111011000001000\n").is_err());
+    assert!(parse_hack("000000000000002\n").is_err());
+}
+
+#[test]
+fn test_step() {
+    // @5, D=A, @100, M=D, AM=M+1
+    let mut computer = assemble("@5\nD=A\n@100\nM=D\nAM=M+1\n");
+    assert_eq!(None, computer.step());
+    assert_eq!(5, computer.a());
+    computer.step();
+    assert_eq!(5, computer.d());
+    computer.step();
+    assert_eq!(
+        Some(MemoryWrite {
+            address: 100,
+            value: 5
+        }),
+        computer.step()
+    );
+    // M is written to the address A held before the instruction
+    assert_eq!(
+        Some(MemoryWrite {
+            address: 100,
+            value: 6
+        }),
+        computer.step()
+    );
+    assert_eq!(6, computer.read(100));
+    assert_eq!(6, computer.a());
+    assert_eq!(5, computer.pc());
+    assert_eq!(5, computer.cycles());
+}
+
+#[test]
+fn test_jumps() {
+    let program = "@10\nD=-A\n@6\nD;JLT\n@0\n0;JMP\n@7\nD;JGE\n@9\n0;JMP\n";
+    let mut computer = assemble(program);
+    for _ in 0..4 {
+        computer.step();
+    }
+    assert_eq!(6, computer.pc());
+    computer.step();
+    computer.step();
+    assert_eq!(8, computer.pc());
+}
+
+#[test]
+fn test_keyboard_is_read_only() {
+    let mut computer = assemble("@KBD\nM=1\nD=M\n");
+    computer.set_key(65);
+    computer.run(3);
+    assert_eq!(65, computer.key());
+    assert_eq!(65, computer.d());
+}
+
+#[test]
+fn test_run_mult() {
+    let mut computer = assemble(MULT);
+    computer.ram_mut()[0] = 6;
+    computer.ram_mut()[1] = 7;
+    let cycles = computer.run(1000);
+    assert!(computer.is_halted());
+    assert!(cycles < 1000);
+    assert_eq!(42, computer.ram()[2]);
+}
+
+#[test]
+fn test_run_rect() {
+    let mut computer = assemble(RECT);
+    computer.write(0, 4);
+    computer.run(1000);
+    for row in 0..4 {
+        assert_eq!(0xffff, computer.read((SCREEN + row * 32) as u16));
+    }
+    assert_eq!(0, computer.read((SCREEN + 4 * 32) as u16));
+    assert_eq!(0, computer.read(KBD as u16 + 1));
+}
+
+#[test]
+fn test_run_respects_max_cycles() {
+    let mut computer = assemble("(LOOP)\n@LOOP\nD;JEQ\n");
+    assert_eq!(50, computer.run(50));
+    computer.reset();
+    assert_eq!(0, computer.pc());
+}
+
+#[test]
+fn test_frame_from_screen() {
+    let mut computer = assemble(RECT);
+    computer.write(0, 2);
+    computer.run(1000);
+    let frame = Frame::from_screen(computer.screen());
+    assert_eq!(
+        (SCREEN_WIDTH, SCREEN_HEIGHT),
+        (frame.width(), frame.height())
+    );
+    assert!(frame.pixel(0, 0) && frame.pixel(15, 1));
+    assert!(!frame.pixel(16, 0) && !frame.pixel(0, 2));
+
+    // the least significant bit is the leftmost pixel
+    let mut screen = vec![0u16; 8192];
+    screen[33] = 0b10;
+    let frame = Frame::from_screen(&screen);
+    assert!(frame.pixel(17, 1));
+    assert!(!frame.pixel(16, 1));
+}
+
+#[test]
+fn test_frame_pbm() {
+    let mut frame = Frame::new(10, 2);
+    frame.set_pixel(0, 0, true);
+    frame.set_pixel(9, 1, true);
+    let pbm = frame.to_pbm();
+    assert_eq!(b"P4\n10 2\n", &pbm[..8]);
+    assert_eq!(frame, Frame::from_pbm(&pbm).unwrap());
+
+    let plain = b"P1\n# comment\n3 2\n1 0 0\n0 0 1\n";
+    let frame = Frame::from_pbm(plain).unwrap();
+    assert!(frame.pixel(0, 0) && frame.pixel(2, 1) && !frame.pixel(1, 0));
+    assert!(Frame::from_pbm(b"P4\n3 2\n").is_err());
+}
+
+#[test]
+fn test_frame_png() {
+    let mut frame = Frame::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+    frame.set_pixel(3, 4, true);
+    frame.set_pixel(511, 255, true);
+    let png = frame.to_png().unwrap();
+    assert_eq!(frame, Frame::from_png(&png).unwrap());
+}
+
+#[test]
+fn test_frame_diff() {
+    let golden = Frame::new(4, 4);
+    let mut frame = Frame::new(4, 4);
+    assert!(frame.diff(&golden).unwrap().is_empty());
+    frame.set_pixel(2, 1, true);
+    frame.set_pixel(0, 3, true);
+    let diff = frame.diff(&golden).unwrap();
+    assert_eq!(2, diff.pixels);
+    assert_eq!(Some((2, 1)), diff.first);
+    assert!(frame.diff(&Frame::new(4, 5)).is_err());
+}
+
+#[test]
+fn test_frame_text() {
+    let mut frame = Frame::new(4, 5);
+    frame.set_pixel(0, 0, true);
+    frame.set_pixel(1, 3, true);
+    frame.set_pixel(3, 4, true);
+    // dots 1 and 8 in the first cell, dot 7 below it
+    assert_eq!(
+        vec!["\u{2881}\u{2800}", "\u{2800}\u{2808}"],
+        frame.to_braille()
+    );
+    assert_eq!(
+        vec!["\u{2580}   ", " \u{2584}  ", "   \u{2580}"],
+        frame.to_half_blocks()
+    );
+
+    let scaled = frame.scaled(2);
+    assert_eq!((2, 3), (scaled.width(), scaled.height()));
+    assert!(scaled.pixel(0, 0) && scaled.pixel(0, 1) && scaled.pixel(1, 2));
+    assert!(!scaled.pixel(1, 0) && !scaled.pixel(0, 2));
+    assert_eq!(frame, frame.scaled(1));
+
+    let screen = Frame::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+    assert_eq!(64, screen.to_braille().len());
+    assert_eq!(128, screen.scaled(2).to_braille()[0].chars().count());
+}
+
+#[test]
+fn test_key_code() {
+    assert_eq!(keyboard::UP, key_code("UP").unwrap());
+    assert_eq!(keyboard::LEFT, key_code("left").unwrap());
+    assert_eq!(keyboard::NEWLINE, key_code("newline").unwrap());
+    assert_eq!(152, key_code("F12").unwrap());
+    assert_eq!(32, key_code("space").unwrap());
+    assert_eq!(97, key_code("a").unwrap());
+    assert_eq!(70, key_code("F").unwrap());
+    assert_eq!(0, key_code("none").unwrap());
+    assert_eq!(65, key_code("65").unwrap());
+    assert!(key_code("F13").is_err());
+    assert!(key_code("shift").is_err());
+}
+
+#[test]
+fn test_key_script_parse() {
+    let script =
+        KeyScript::parse("// press and release\n500 UP\n\n100 a // first\n900 0\n").unwrap();
+    assert_eq!(
+        &[
+            KeyEvent {
+                cycle: 100,
+                code: 97
+            },
+            KeyEvent {
+                cycle: 500,
+                code: 131
+            },
+            KeyEvent {
+                cycle: 900,
+                code: 0
+            },
+        ],
+        script.events()
+    );
+    assert_eq!(None, script.key_at(99));
+    assert_eq!(Some(97), script.key_at(100));
+    assert_eq!(Some(131), script.key_at(899));
+    assert!(KeyScript::parse("100\n").is_err());
+    assert!(KeyScript::parse("soon UP\n").is_err());
+}
+
+#[test]
+fn test_key_script_run_fill() {
+    let mut computer = assemble(FILL);
+    let script = KeyScript::parse("0 UP\n150000 none\n").unwrap();
+    // Fill polls KBD only after painting all 8192 words, 15 cycles each
+    assert_eq!(140_000, script.run(&mut computer, 140_000));
+    assert_eq!(keyboard::UP, computer.key());
+    assert!(computer.screen().iter().all(|word| *word == 0xffff));
+    // running in chunks replays the release at its cycle
+    for _ in 0..5 {
+        script.run(&mut computer, 50_000);
+    }
+    assert_eq!(0, computer.key());
+    assert!(computer.screen().iter().all(|word| *word == 0));
+}
+
+#[test]
+fn test_program_symbols() {
+    let program = Program::assemble(MULT).unwrap();
+    let loop_address = program.label_address("LOOP").unwrap();
+    assert_eq!("@R1", program.source_line(loop_address).unwrap().command);
+    assert_eq!(Some("LOOP"), program.enclosing_label(loop_address + 3));
+    assert_eq!(None, program.enclosing_label(0));
+    assert_eq!(Some(2), program.symbol("R2"));
+    assert_eq!(None, program.label_address("R2"));
+    assert_eq!(
+        program.words().len(),
+        program.label_address("END").unwrap() as usize + 2
+    );
+}
+
+#[test]
+fn test_debugger_breakpoints() {
+    let mut debugger = Debugger::new(MULT).unwrap();
+    debugger.computer_mut().write(0, 6);
+    debugger.computer_mut().write(1, 7);
+    let loop_address = debugger.add_breakpoint("LOOP").unwrap();
+    assert!(debugger.add_breakpoint("NOWHERE").is_err());
+    assert_eq!(2, debugger.add_watch("R2").unwrap());
+    assert!(debugger.add_watch("LOOP").is_err());
+
+    assert_eq!(Stop::Breakpoint(loop_address), debugger.resume(1000));
+    assert_eq!(0, debugger.computer().read(2));
+    // continuing moves past the breakpoint to its next hit
+    assert_eq!(Stop::Breakpoint(loop_address), debugger.resume(1000));
+    assert_eq!(6, debugger.computer().read(2));
+
+    assert!(!debugger.toggle_breakpoint("LOOP").unwrap());
+    assert_eq!(Stop::Done, debugger.run_to("END", 1000).unwrap());
+    assert_eq!(42, debugger.computer().read(2));
+    assert_eq!(Stop::Halted, debugger.resume(1000));
+
+    debugger.restart();
+    assert_eq!(0, debugger.computer().pc());
+    assert_eq!(Stop::Limit, debugger.resume(3));
+}
+
+#[test]
+fn test_debugger_step_over() {
+    let mut debugger = Debugger::new(CALLS).unwrap();
+    let ret1 = debugger.location("RET1").unwrap();
+    for _ in 0..9 {
+        assert_eq!(Stop::Done, debugger.step_over(1000));
+    }
+    // the next instruction jumps to DOUBLE and returns to RET1
+    assert_eq!(ret1 - 1, debugger.computer().pc());
+    assert_eq!(Stop::Done, debugger.step_over(1000));
+    assert_eq!(ret1, debugger.computer().pc());
+    assert_eq!(6, debugger.computer().read(0));
+
+    // a single step enters the call instead
+    let double = debugger.location("DOUBLE").unwrap();
+    for _ in 0..6 {
+        debugger.step();
+    }
+    assert_eq!(double, debugger.computer().pc());
+
+    // breakpoints inside the callee still stop a step over
+    debugger.restart();
+    debugger.add_breakpoint("DOUBLE").unwrap();
+    debugger.run_to("9", 1000).unwrap();
+    assert_eq!(Stop::Breakpoint(double), debugger.step_over(1000));
+}
+
+#[test]
+fn test_profiler_calls() {
+    let program = Program::assemble(CALLS).unwrap();
+    let mut computer = Computer::from_program(program.words());
+    let mut profiler = Profiler::new(&program);
+    assert_eq!(28, profiler.run(&mut computer, 1000));
+    assert_eq!(28, profiler.total());
+    assert_eq!(
+        2,
+        profiler.counts()[program.label_address("DOUBLE").unwrap() as usize]
+    );
+    assert_eq!(
+        vec![("DOUBLE", 12), ("(top)", 10), ("RET1", 6)],
+        profiler.flat()
+    );
+    assert_eq!(
+        "(top) 10\n(top);DOUBLE 12\n(top);RET1 6\n",
+        profiler.collapsed()
+    );
+    assert!(profiler.flat_report().contains("   42.86  DOUBLE"));
+}
+
+#[test]
+fn test_profiler_recursion() {
+    let program = Program::assemble(RECURSION).unwrap();
+    let mut computer = Computer::from_program(program.words());
+    let mut profiler = Profiler::new(&program);
+    profiler.run(&mut computer, 1000);
+    assert!(computer.is_halted());
+    let collapsed = profiler.collapsed();
+    let stacks: Vec<&str> = collapsed
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().0)
+        .collect();
+    assert_eq!(
+        vec![
+            "(top)",
+            "(top);Rec.down",
+            "(top);Rec.down;Rec.down",
+            "(top);Rec.down;Rec.down;Rec.down"
+        ],
+        stacks
+    );
+    // the local labels belong to the function
+    let names: Vec<&str> = profiler.flat().iter().map(|(name, _)| *name).collect();
+    assert_eq!(vec!["Rec.down", "(top)"], names);
+}
+
+#[test]
+fn test_profiler_loop_is_not_a_call() {
+    let program = Program::assemble(MULT).unwrap();
+    let mut computer = Computer::from_program(program.words());
+    computer.write(0, 4);
+    computer.write(1, 5);
+    let mut profiler = Profiler::new(&program);
+    profiler.run(&mut computer, 1000);
+    assert_eq!(20, computer.read(2));
+    for line in profiler.collapsed().lines() {
+        assert!(
+            line.starts_with("(top) ") || line.starts_with("(top);LOOP "),
+            "{}",
+            line
+        );
+    }
+}
+
+#[test]
+fn test_decode() {
+    // @2, D=D+A, (LOOP) @2, 0;JMP
+    let rom = [2, 0b1110000010010000, 2, 0b1110101010000111];
+    let ops = decode(&rom);
+    assert_eq!(
+        Op::LoadCompute {
+            value: 2,
+            alu: Alu::from_control(0b000010),
+            m: false,
+            dest: 0b010,
+            jump: 0,
+        },
+        ops[0]
+    );
+    // jumps may land on the C-instruction of a fused pair
+    assert!(matches!(ops[1], Op::Compute { dest: 0b010, .. }));
+    assert_eq!(Op::Halt, ops[2]);
+    for control in 0..64 {
+        assert_eq!(
+            alu(7, 12, control),
+            Alu::from_control(control).compute(7, 12)
+        );
+    }
+}
+
+#[test]
+fn test_run_matches_step() {
+    let mut fast = assemble(PONG);
+    let mut slow = assemble(PONG);
+    // an odd chunk size splits fused pairs between calls
+    for _ in 0..100 {
+        fast.run(9_999);
+    }
+    for _ in 0..999_900 {
+        slow.step();
+    }
+    assert_eq!(slow.cycles(), fast.cycles());
+    assert_eq!(
+        (slow.a(), slow.d(), slow.pc()),
+        (fast.a(), fast.d(), fast.pc())
+    );
+    assert!(slow.ram() == fast.ram());
+
+    // small chunks stop at every offset inside a basic block
+    for chunk in (1..=13).cycle().take(2_000) {
+        fast.run(chunk);
+        for _ in 0..chunk {
+            slow.step();
+        }
+        assert_eq!(
+            (slow.a(), slow.d(), slow.pc()),
+            (fast.a(), fast.d(), fast.pc())
+        );
+    }
+    assert!(slow.ram() == fast.ram());
+}
+
+#[test]
+fn test_run_after_rom_change() {
+    let mut computer = assemble(RECT);
+    computer.run(10);
+    // replace the program by `@5, D=A, (END) @2, 0;JMP`
+    computer.rom_mut()[..4].copy_from_slice(&[5, 0b1110110000010000, 2, 0b1110101010000111]);
+    computer.set_pc(0);
+    assert_eq!(2, computer.run(100));
+    assert_eq!(5, computer.d());
+    assert!(computer.is_halted());
+}
+
+#[test]
+fn test_recorder_reverse() {
+    let mut computer = assemble(MULT);
+    computer.write(0, 3);
+    computer.write(1, 4);
+    let start = Snapshot::of(&computer);
+    let mut recorder = Recorder::new(computer);
+    assert_eq!(Stop::Halted, recorder.resume(1000));
+    assert_eq!(12, recorder.computer().read(2));
+    let cycles = recorder.computer().cycles();
+    assert_eq!(cycles as usize, recorder.history());
+
+    // the last write to R2 adds the final R0
+    recorder.add_watchpoint(2);
+    assert_eq!(Stop::Watchpoint(2), recorder.reverse_resume());
+    assert_eq!(9, recorder.computer().read(2));
+    assert_eq!("M=D+M", program_line(recorder.computer().pc()));
+    assert_eq!(Stop::Watchpoint(2), recorder.step());
+    assert_eq!(12, recorder.computer().read(2));
+
+    recorder.remove_watchpoint(2);
+    assert_eq!(Stop::HistoryStart, recorder.reverse_resume());
+    assert_eq!(start, recorder.snapshot());
+    assert_eq!(Stop::HistoryStart, recorder.reverse_step());
+}
+
+fn program_line(address: u16) -> String {
+    Program::assemble(MULT)
+        .unwrap()
+        .source_line(address)
+        .unwrap()
+        .command
+        .clone()
+}
+
+#[test]
+fn test_recorder_limit() {
+    let mut recorder = Recorder::new(assemble(MULT));
+    recorder.computer_mut().write(1, 10);
+    recorder.set_limit(5);
+    recorder.resume(20);
+    assert_eq!(5, recorder.history());
+    assert_eq!(Stop::HistoryStart, recorder.reverse_resume());
+    assert_eq!(15, recorder.computer().cycles());
+}
+
+#[test]
+fn test_snapshot_save_restore() {
+    let mut recorder = Recorder::new(assemble(RECT));
+    recorder.computer_mut().write(0, 4);
+    recorder.resume(50);
+    let snapshot = recorder.snapshot();
+    assert_eq!(snapshot, Snapshot::parse(&snapshot.to_text()).unwrap());
+
+    let path = std::env::temp_dir().join(format!("snapshot_{}.txt", std::process::id()));
+    snapshot.save(&path).unwrap();
+    recorder.resume(1000);
+    assert_ne!(snapshot, recorder.snapshot());
+    recorder.restore(&Snapshot::load(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(snapshot, recorder.snapshot());
+    assert_eq!(0, recorder.history());
+
+    assert!(Snapshot::parse("hack-snapshot 1\nram 30000 1\n").is_err());
+    assert!(Snapshot::parse("hack-snapshot 1\na 70000\n").is_err());
+    assert!(Snapshot::parse("a 1\n").is_err());
+}
+
+#[test]
+fn test_debugger_reverse() {
+    let mut debugger = Debugger::new(MULT).unwrap();
+    debugger.computer_mut().write(0, 2);
+    debugger.computer_mut().write(1, 3);
+    assert!(debugger.toggle_watchpoint("R1").unwrap());
+    // R1 counts down once per iteration
+    assert_eq!(Stop::Watchpoint(1), debugger.resume(1000));
+    assert_eq!(2, debugger.computer().read(1));
+    // back to right before the write, then on to the start of the loop
+    assert_eq!(Stop::Watchpoint(1), debugger.reverse_resume());
+    assert_eq!(3, debugger.computer().read(1));
+    let loop_address = debugger.add_breakpoint("LOOP").unwrap();
+    assert_eq!(Stop::Breakpoint(loop_address), debugger.reverse_resume());
+    assert_eq!(Stop::Done, debugger.reverse_step());
+    assert_eq!(Stop::HistoryStart, debugger.reverse_resume());
+    assert_eq!(0, debugger.computer().pc());
+    assert!(!debugger.toggle_watchpoint("R1").unwrap());
+    assert!(debugger.toggle_watchpoint("LOOP").is_err());
+}
+
+fn monitor(command: &str) -> String {
+    let hex: String = command
+        .bytes()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("qRcmd,{}", hex)
+}
+
+#[test]
+fn test_gdb_packets() {
+    assert_eq!("$OK#9a", gdb::frame("OK"));
+    let program = Program::assemble(CALLS).unwrap();
+    let computer = Computer::from_program(program.words());
+    let mut stub = GdbStub::new(computer, program.labels().to_vec());
+    let reply = |data: &str| Response::Reply(data.to_string());
+
+    assert_eq!(reply("S05"), stub.handle("?"));
+    assert_eq!(reply(""), stub.handle("\u{fffd}m0,2"));
+    // pc is a byte address in ROM, then A and D
+    assert_eq!(reply("0000010000000000"), stub.handle("g"));
+    match stub.handle("qXfer:features:read:target.xml:0,1000") {
+        Response::Reply(xml) => assert!(xml.starts_with("l<?xml")),
+        other => panic!("unexpected {:?}", other),
+    }
+    // ROM word 0 is @3
+    assert_eq!(reply("0300"), stub.handle("m10000,2"));
+
+    match stub.handle(&monitor("break DOUBLE")) {
+        Response::Output(_, ok) => assert_eq!("OK", ok),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(stub.breakpoints().contains(&18));
+    assert_eq!(Response::Resume, stub.handle("c"));
+    assert_eq!(Some(String::from("T05swbreak:;")), stub.run(1000));
+    assert_eq!(reply("24000100"), stub.handle("p0"));
+
+    // DOUBLE reads R0, then writes it
+    assert_eq!(reply("OK"), stub.handle("Z3,0,2"));
+    assert_eq!(Some(String::from("T05rwatch:0;")), stub.run(1000));
+    assert_eq!(reply("OK"), stub.handle("z3,0,2"));
+    assert_eq!(reply("OK"), stub.handle("Z2,0,2"));
+    assert_eq!(Some(String::from("T05watch:0;")), stub.run(1000));
+    assert_eq!(reply("0600"), stub.handle("m0,2"));
+    assert_eq!(reply("OK"), stub.handle("M2,2:0500"));
+    assert_eq!(5, stub.computer().read(1));
+    assert_eq!(reply("OK"), stub.handle("P2=0700"));
+    assert_eq!(7, stub.computer().d());
+
+    assert_eq!(reply("OK"), stub.handle("z2,0,2"));
+    assert_eq!(reply("OK"), stub.handle("z0,10024,2"));
+    assert_eq!(reply("S05"), stub.handle("s"));
+    assert_eq!(Some(String::from("W00")), stub.run(1000));
+    assert_eq!(12, stub.computer().read(0));
+    assert_eq!(reply("E01"), stub.handle("Z0,10,2"));
+    assert_eq!(Response::Close(Some(String::from("OK"))), stub.handle("D"));
+}
+
+#[test]
+fn test_gdb_packet_ranges() {
+    let program = Program::assemble(CALLS).unwrap();
+    let computer = Computer::from_program(program.words());
+    let mut stub = GdbStub::new(computer, program.labels().to_vec());
+    let reply = |data: &str| Response::Reply(data.to_string());
+
+    // ranges whose end doesn't fit in 32 bits are rejected
+    assert_eq!(reply("E01"), stub.handle("Z2,fffffffe,4"));
+    assert_eq!(reply("E01"), stub.handle("Z2,2,fffffffe"));
+    assert_eq!(reply("E01"), stub.handle("Mffffffff,2:0000"));
+    assert_eq!(reply("E01"), stub.handle("mffffffff,2"));
+    // the rejected watchpoints never trigger
+    assert_eq!(Response::Resume, stub.handle("c"));
+    assert_eq!(Some(String::from("W00")), stub.run(1000));
+}
+
+fn read_reply(stream: &mut TcpStream) -> String {
+    let mut reply = Vec::new();
+    let mut byte = [0u8; 1];
+    while reply.last() != Some(&b'#') {
+        stream.read_exact(&mut byte).unwrap();
+        if reply.is_empty() && byte[0] == b'+' {
+            continue;
+        }
+        reply.push(byte[0]);
+    }
+    let mut sum = [0u8; 2];
+    stream.read_exact(&mut sum).unwrap();
+    stream.write_all(b"+").unwrap();
+    String::from_utf8(reply[1..reply.len() - 1].to_vec()).unwrap()
+}
+
+#[test]
+fn test_gdb_serve() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut stub = GdbStub::new(assemble(PONG), Vec::new());
+        gdb::serve(&mut stub, stream).unwrap();
+    });
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(gdb::frame("m10000,2").as_bytes()).unwrap();
+    assert_eq!("0001", read_reply(&mut stream));
+    // Pong never halts, so continuing only stops on an interrupt
+    stream.write_all(gdb::frame("c").as_bytes()).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    stream.write_all(&[0x03]).unwrap();
+    assert_eq!("T02", read_reply(&mut stream));
+    // a non-ASCII command gets the empty reply for unsupported packets
+    stream.write_all(b"$\xff#ff").unwrap();
+    assert_eq!("", read_reply(&mut stream));
+    stream.write_all(gdb::frame("D").as_bytes()).unwrap();
+    assert_eq!("OK", read_reply(&mut stream));
+    server.join().unwrap();
+}
+
+#[test]
+fn test_script_parse() {
+    let script = TestScript::parse(
+        "load Mult.asm, // comment
+        output-list RAM[0]%D2.6.2 PC%B1.16.1;
+        set RAM[1] -1, set A %X10;
+        repeat 3 { ticktock; }
+        while RAM[0] <> 5 { ticktock; }
+        echo \"done, really\";",
+    )
+    .unwrap();
+    let commands = script.commands();
+    assert_eq!(7, commands.len());
+    assert_eq!(Command::Load(String::from("Mult.asm")), commands[0]);
+    assert!(matches!(&commands[1], Command::OutputList(columns) if columns.len() == 2));
+    assert!(matches!(commands[2], Command::Set(_, 0xffff)));
+    assert!(matches!(commands[3], Command::Set(_, 16)));
+    assert_eq!(Command::Repeat(3, vec![Command::Ticktock]), commands[4]);
+    assert_eq!(Command::Echo(String::from("done, really")), commands[6]);
+
+    assert!(TestScript::parse("frobnicate;").is_err());
+    assert!(TestScript::parse("output-list RAM[0]%Q1.2.3;").is_err());
+    assert!(TestScript::parse("set RAM[0] 70000;").is_err());
+    assert!(TestScript::parse("repeat 3 { ticktock;").is_err());
+}
+
+#[test]
+fn test_script_run_mult() {
+    let dir = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../projects/04/mult"));
+    let source = std::fs::read_to_string(dir.join("Mult.tst")).unwrap();
+    let outcome = TestScript::parse(&source).unwrap().run(dir).unwrap();
+    assert_eq!(Some(String::from("Mult.out")), outcome.output_file);
+    assert_eq!(None, outcome.mismatch);
+    assert!(outcome
+        .output
+        .starts_with("|  RAM[0]  |  RAM[1]  |  RAM[2]  |\n"));
+    assert!(outcome
+        .output
+        .ends_with("|       6  |       7  |      42  |\n"));
+}
+
+#[test]
+fn test_script_run_formats() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("Calls.asm"), CALLS).unwrap();
+    let script = TestScript::parse(
+        "load Calls.asm,
+        output-list time%S1.4.1 RAM[0]%X1.4.1 D%B1.8.1 RAM[3032]%D1.6.1 PC%D1.2.1;
+        output;
+        while PC <> 16 { ticktock; }
+        output;
+        set RAM[0] -2, repeat 1001 { ticktock; }
+        output;",
+    )
+    .unwrap();
+    let outcome = script.run(dir.path()).unwrap();
+    assert_eq!(
+        "| time |RAM[0]|    D     |RAM[3032| PC |
+| 0    | 0000 | 00000000 |      0 |  0 |
+| 28   | 000C | 00000110 |      0 | 16 |
+| 1029 | FFFE | 00000110 |      0 | 17 |
+",
+        outcome.output
+    );
+    assert_eq!(None, outcome.compare_to);
+}
+
+#[test]
+fn test_script_repeat_until_halted() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("Calls.asm"), CALLS).unwrap();
+    // an unbounded repeat runs into the halt loop and ends there
+    let script = TestScript::parse(
+        "load Calls.asm,
+        repeat { ticktock; }
+        output-list RAM[0]%D1.6.1;
+        output;",
+    )
+    .unwrap();
+    let outcome = script.run(dir.path()).unwrap();
+    assert_eq!("| RAM[0] |\n|     12 |\n", outcome.output);
+}
+
+#[test]
+fn test_compare() {
+    let expected = "|time| RAM[0] |  out  |\r\n|0+  |     3 |*******|\n|1   |    1* |   -1  |\n";
+    assert!(compare(
+        "|time| RAM[0] |  out  |  \n|0+  |     3 |     7 |\n|1   |    12 |   -1  |\n",
+        expected
+    )
+    .is_empty());
+
+    let mismatches = compare(
+        "|time| RAM[0] |  out  |\n|0+  |     4 |   1   |\n|1   |     1 |   -1  |\n",
+        expected,
+    );
+    assert_eq!(
+        vec![
+            Mismatch {
+                row: 2,
+                column: Some(String::from("RAM[0]")),
+                expected: String::from("3"),
+                actual: String::from("4"),
+            },
+            Mismatch {
+                row: 3,
+                column: Some(String::from("RAM[0]")),
+                expected: String::from("1*"),
+                actual: String::from("1"),
+            },
+        ],
+        mismatches
+    );
+    assert_eq!(
+        "line 2, column RAM[0]: expected `3`, got `4`",
+        mismatches[0].to_string()
+    );
+
+    let mismatches = compare(
+        "|time| RAM[0] |  out  |\n|0+  |     3 |     7 |\n",
+        expected,
+    );
+    assert_eq!(1, mismatches.len());
+    assert_eq!((3, None), (mismatches[0].row, mismatches[0].column.clone()));
+    assert_eq!("", mismatches[0].actual);
+}
+
+#[test]
+fn test_compare_cpu_output() {
+    let dir = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../projects/05"));
+    let output = std::fs::read_to_string(dir.join("CPU.out")).unwrap();
+    let expected = std::fs::read_to_string(dir.join("CPU.cmp")).unwrap();
+    assert!(compare(&output, &expected).is_empty());
+    // outM is a wildcard where writeM is 0
+    let broken = output.replacen("|  12345|", "|  12346|", 1);
+    assert!(compare(&broken, &expected).is_empty());
+    let broken = output.replacen("|    1|  12345 |", "|    2|  12345 |", 1);
+    let mismatches = compare(&broken, &expected);
+    assert_eq!(Some(String::from("pc")), mismatches[0].column);
+}
+
+fn trace_of(source: &str, format: Format) -> Vec<u8> {
+    let mut computer = assemble(source);
+    let mut writer = TraceWriter::new(Vec::new(), format).unwrap();
+    trace::trace(&mut computer, 1000, &mut writer).unwrap();
+    writer.into_inner()
+}
+
+fn read_trace(bytes: &[u8]) -> Vec<Record> {
+    TraceReader::new(bytes)
+        .unwrap()
+        .map(|record| record.unwrap())
+        .collect()
+}
+
+#[test]
+fn test_trace_formats() {
+    let csv = trace_of(CALLS, Format::Csv);
+    let text = String::from_utf8(csv.clone()).unwrap();
+    let mut lines = text.lines();
+    assert_eq!(
+        Some("cycle,pc,word,instruction,a,d,address,value"),
+        lines.next()
+    );
+    assert_eq!(Some("0,0,0000000000000011,@3,3,0,,"), lines.next());
+    assert_eq!(Some("1,1,1110110000010000,D=A,3,3,,"), lines.next());
+    assert_eq!(Some("3,3,1110001100001000,M=D,0,3,0,3"), lines.nth(1));
+
+    let binary = trace_of(CALLS, Format::Binary);
+    assert!(binary.starts_with(b"HACKTRC1"));
+    let records = read_trace(&csv);
+    assert_eq!(28, records.len());
+    assert_eq!(records, read_trace(&binary));
+    assert_eq!(
+        Some(MemoryWrite {
+            address: 0,
+            value: 12
+        }),
+        records[27 - 3].write
+    );
+    assert!(TraceReader::new(&b"pc,a,d\n"[..]).is_err());
+    assert!(TraceReader::new(&b"HACKTRC1\x01\x00"[..])
+        .unwrap()
+        .next()
+        .unwrap()
+        .is_err());
+}
+
+fn reader(bytes: &[u8]) -> TraceReader<&[u8]> {
+    TraceReader::new(bytes).unwrap()
+}
+
+#[test]
+fn test_trace_diff() {
+    // doubles R0 twice inline, to the same writes as CALLS
+    let inline = "
+    @3
+    D=A
+    @R0
+    M=D
+    M=D+M
+    D=M
+    M=D+M
+(END)
+    @END
+    0;JMP
+";
+    let csv = trace_of(CALLS, Format::Csv);
+    let binary = trace_of(CALLS, Format::Binary);
+    let other = trace_of(inline, Format::Binary);
+
+    assert_eq!(
+        None,
+        trace::diff(reader(&csv), reader(&binary), false).unwrap()
+    );
+    let divergence = trace::diff(reader(&csv), reader(&other), false)
+        .unwrap()
+        .unwrap();
+    assert_eq!(4, divergence.index);
+    assert_eq!(Some(4), divergence.left.map(|record| record.pc));
+    assert!(divergence
+        .to_string()
+        .contains("left:  cycle 4 pc 4 @10 A=10 D=3"));
+
+    // only R15 is written in addition, with the return addresses
+    let divergence = trace::diff(reader(&csv), reader(&other), true)
+        .unwrap()
+        .unwrap();
+    assert_eq!(1, divergence.index);
+    assert_eq!(
+        Some(MemoryWrite {
+            address: 15,
+            value: 10
+        }),
+        divergence.left.unwrap().write
+    );
+    let short = trace_of(inline, Format::Binary);
+    let shorter = &short[..short.len() - 24];
+    let divergence = trace::diff(reader(&short), reader(shorter), false)
+        .unwrap()
+        .unwrap();
+    assert_eq!(None, divergence.right);
+}
@@ -1,17 +1,19 @@
 #[macro_use]
 extern crate simple_error;
-extern crate regex;
-use lazy_static::lazy_static;
-use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::fs;
-use std::io::{BufRead, Cursor, LineWriter, Write};
-use std::path::Path;
-use std::{error::Error, fmt::DebugList};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::error::Error;
 
 type BoxResult<T> = Result<T, Box<dyn Error>>;
 
+/// Statics live in RAM 16 to 255, below the stack.
+pub const MAX_STATICS: usize = 256 - 16;
+/// The largest constant an A-instruction can load.
+pub const MAX_CONSTANT: u16 = 32767;
+
 #[derive(Debug, PartialEq)]
 pub enum Command {
     ARITHMETIC,
@@ -24,101 +26,342 @@ pub enum Command {
     CALL,
 }
 
-lazy_static! {
-    static ref RE_ARITHMETIC: Regex = Regex::new(r"^(?P<op>(add|sub|neg|eq|gt|lt|and|or|not))").unwrap();
-    static ref RE_PUSHPOP: Regex = Regex::new(r"^(?P<command>(push|pop)).(?P<segment>(argument|local|static|constant|this|that|pointer|temp)).(?P<index>\d+)").unwrap();
-    static ref RE_LABEL: Regex = Regex::new(r"^label.(?P<name>\w+)").unwrap();
-    static ref RE_GOTO: Regex = Regex::new(r"^goto.(?P<name>\w+)").unwrap();
-    static ref RE_IF: Regex = Regex::new(r"^if-goto.(?P<name>\w+)").unwrap();
-    static ref RE_FUNCTION: Regex = Regex::new(r"^function.(?P<name>\w+\.\w+).(?P<k>\d+)").unwrap();
-    static ref RE_CALL: Regex = Regex::new(r"^call.(?P<name>\w+\.\w+).(?P<n>\d+)").unwrap();
-    static ref RE_RETURN: Regex = Regex::new(r"^return$").unwrap();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Segment {
+    Argument,
+    Local,
+    Static,
+    Constant,
+    This,
+    That,
+    Pointer,
+    Temp,
+}
+
+impl Segment {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let segment = match name {
+            "argument" => Segment::Argument,
+            "local" => Segment::Local,
+            "static" => Segment::Static,
+            "constant" => Segment::Constant,
+            "this" => Segment::This,
+            "that" => Segment::That,
+            "pointer" => Segment::Pointer,
+            "temp" => Segment::Temp,
+            _ => return None,
+        };
+        Some(segment)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Segment::Argument => "argument",
+            Segment::Local => "local",
+            Segment::Static => "static",
+            Segment::Constant => "constant",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Pointer => "pointer",
+            Segment::Temp => "temp",
+        }
+    }
 }
 
-pub struct CodeWriter {
-    out_writer: LineWriter<fs::File>,
-    operator_count: HashMap<String, u32>,
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+impl ArithOp {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let op = match name {
+            "add" => ArithOp::Add,
+            "sub" => ArithOp::Sub,
+            "neg" => ArithOp::Neg,
+            "eq" => ArithOp::Eq,
+            "gt" => ArithOp::Gt,
+            "lt" => ArithOp::Lt,
+            "and" => ArithOp::And,
+            "or" => ArithOp::Or,
+            "not" => ArithOp::Not,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ArithOp::Add => "add",
+            ArithOp::Sub => "sub",
+            ArithOp::Neg => "neg",
+            ArithOp::Eq => "eq",
+            ArithOp::Gt => "gt",
+            ArithOp::Lt => "lt",
+            ArithOp::And => "and",
+            ArithOp::Or => "or",
+            ArithOp::Not => "not",
+        }
+    }
+
+    /// Whether the operation takes a single operand off the stack.
+    pub fn is_unary(self) -> bool {
+        self == ArithOp::Neg || self == ArithOp::Not
+    }
+}
+
+impl fmt::Display for ArithOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A parsed VM command; its `Display` is the VM source it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmCommand {
+    Arithmetic(ArithOp),
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    Label(String),
+    Goto(String),
+    IfGoto(String),
+    Function(String, u16),
+    Call(String, u16),
+    Return,
+}
+
+impl VmCommand {
+    pub fn command_type(&self) -> Command {
+        match self {
+            VmCommand::Arithmetic(_) => Command::ARITHMETIC,
+            VmCommand::Push(..) | VmCommand::Pop(..) => Command::PUSHPOP,
+            VmCommand::Label(_) => Command::LABEL,
+            VmCommand::Goto(_) => Command::GOTO,
+            VmCommand::IfGoto(_) => Command::IF,
+            VmCommand::Function(..) => Command::FUNCTION,
+            VmCommand::Call(..) => Command::CALL,
+            VmCommand::Return => Command::RETURN,
+        }
+    }
 }
 
-impl CodeWriter {
-    pub fn new(filepath: &Path) -> Self {
-        let fw = fs::File::create(filepath).expect("Unable to create file");
-        let fw = LineWriter::new(fw);
+impl fmt::Display for VmCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmCommand::Arithmetic(op) => write!(f, "{}", op),
+            VmCommand::Push(segment, index) => write!(f, "push {} {}", segment, index),
+            VmCommand::Pop(segment, index) => write!(f, "pop {} {}", segment, index),
+            VmCommand::Label(label) => write!(f, "label {}", label),
+            VmCommand::Goto(label) => write!(f, "goto {}", label),
+            VmCommand::IfGoto(label) => write!(f, "if-goto {}", label),
+            VmCommand::Function(name, no_locals) => write!(f, "function {} {}", name, no_locals),
+            VmCommand::Call(name, no_args) => write!(f, "call {} {}", name, no_args),
+            VmCommand::Return => write!(f, "return"),
+        }
+    }
+}
+
+/// Translates VM commands into Hack assembly written to `out_writer`.
+pub struct CodeWriter<W: Write> {
+    out_writer: W,
+    operator_count: HashMap<ArithOp, u32>,
+    // the function being translated, labels are scoped to it
+    function_name: String,
+    // calls so far, keeps return address labels unique
+    call_count: u32,
+    // the static variables used so far
+    statics: HashSet<String>,
+    // the functions defined so far
+    functions: HashSet<String>,
+    // whether the bootstrap code calling Sys.init was written
+    bootstrapped: bool,
+    // whether eq/gt/lt, call and return jump to shared subroutines
+    compact: bool,
+    // the shared subroutines jumped to so far, written by `close`
+    routines: BTreeSet<&'static str>,
+}
+
+impl<W: Write> CodeWriter<W> {
+    pub fn new(out_writer: W) -> Self {
         let operator_count = HashMap::new();
         Self {
-            out_writer: fw,
+            out_writer,
             operator_count,
+            function_name: String::new(),
+            call_count: 0,
+            statics: HashSet::new(),
+            functions: HashSet::new(),
+            bootstrapped: false,
+            compact: false,
+            routines: BTreeSet::new(),
         }
     }
 
-    pub fn write(&mut self, line: &str) {
-        self.out_writer
-            .write(&line.as_bytes())
-            .expect("Unable to write line");
+    /// In compact mode `eq`, `gt`, `lt`, `call` and `return` jump to
+    /// subroutines written once at the end of the program, instead of being
+    /// inlined at every use.
+    pub fn set_compact(&mut self, compact: bool) {
+        self.compact = compact;
     }
 
-    pub fn write_push_pop(&mut self, name: &str, command_type: &str, segment: &str, index: usize) {
-        // TODO: can this be done with overloading?
-        // Store the value temporarily with @index and D=A
-        // sets the address to the Stack Pointer address with @0 and A=M
-        // sets the value at M[Address] to temporarily stored value with M=D
-        // increases stack pointer with @0 and M=M+1
+    pub fn write(&mut self, line: &str) -> BoxResult<()> {
+        self.out_writer.write_all(line.as_bytes())?;
+        Ok(())
+    }
 
-        // if segment type is static, add symbol
-        if segment == "static" {
-            self.write(&format!("({}.{})\n", name, index));
+    /// Translates the commands of the file `name`, whose statics are
+    /// `name.i`. Errors name the line of the failing command.
+    pub fn write_commands(&mut self, name: &str, commands: &[(usize, VmCommand)]) -> BoxResult<()> {
+        for (line, command) in commands {
+            if let Err(err) = self.write_command(name, command) {
+                bail!("line {}: {}", line, err);
+            }
         }
+        Ok(())
+    }
 
-        let segment_str = match segment {
-            "local" => String::from("@LCL\nA=M"),
-            "argument" => String::from("@ARG\nA=M"),
-            "this" => String::from("@THIS\nA=M"),
-            "that" => String::from("@THAT\nA=M"),
-            "pointer" => String::from("@3"),
-            "temp" => String::from("@5"),
-            "static" => format!("@{}.{}", name, index),
-            _ => String::from("constant"),
-        };
+    pub fn write_command(&mut self, name: &str, command: &VmCommand) -> BoxResult<()> {
+        match command {
+            VmCommand::Arithmetic(op) => self.write_arithmetic(*op),
+            VmCommand::Push(segment, index) => self.write_push_pop(name, true, *segment, *index),
+            VmCommand::Pop(segment, index) => self.write_push_pop(name, false, *segment, *index),
+            VmCommand::Label(label) => self.write_label(label),
+            VmCommand::Goto(label) => self.write_goto(label),
+            VmCommand::IfGoto(label) => self.write_if(label),
+            VmCommand::Function(function_name, no_locals) => {
+                self.write_function(function_name, *no_locals)
+            }
+            VmCommand::Call(function_name, no_args) => self.write_call(function_name, *no_args),
+            VmCommand::Return => self.write_return(),
+        }
+    }
 
-        let mut index_str = String::from("");
-        // if segment is static, the index is not used in the usual way to address the memory
-        if segment != "static" {
-            index_str = "A=A+1\n".repeat(index);
+    pub fn write_push_pop(
+        &mut self,
+        name: &str,
+        push: bool,
+        segment: Segment,
+        index: u16,
+    ) -> BoxResult<()> {
+        // pointer and temp are fixed registers, so their index is bounded
+        match segment {
+            Segment::Pointer if index > 1 => bail!("pointer {} is out of range 0..1.", index),
+            Segment::Temp if index > 7 => bail!("temp {} is out of range 0..7.", index),
+            Segment::Constant if !push => bail!("Can't pop to constant."),
+            _ => {}
+        }
+        // statics are assembler variables File.i, allocated from RAM 16
+        if segment == Segment::Static {
+            self.statics.insert(format!("{}.{}", name, index));
         }
 
-        let push_value_str = match segment {
-            "constant" => format!("@{}\nD=A", index),
-            "static" => format!("@{}.{}\nD=M", name, index),
-            _ => format!("{}\n{}D=M", segment_str, index_str),
+        // the address of a fixed location, None for the segments addressed
+        // through their base pointer
+        let address = match segment {
+            Segment::Pointer => Some((3 + index).to_string()),
+            Segment::Temp => Some((5 + index).to_string()),
+            Segment::Static => Some(format!("{}.{}", name, index)),
+            _ => None,
+        };
+        let base = match segment {
+            Segment::Local => "LCL",
+            Segment::Argument => "ARG",
+            Segment::This => "THIS",
+            Segment::That => "THAT",
+            _ => "",
         };
 
-        let translated_code = match command_type {
-            "push" => format!(
-                r#"{}
+        let translated_code = match (push, &address) {
+            (true, _) => {
+                // Loads the value into D, then stores it at the stack top
+                // and increases the stack pointer
+                let push_value_str = match (segment, &address) {
+                    (Segment::Constant, _) => format!("@{}\nD=A", index),
+                    (_, Some(address)) => format!("@{}\nD=M", address),
+                    (_, None) => format!("@{}\nD=A\n@{}\nA=D+M\nD=M", index, base),
+                };
+                format!(
+                    r#"{}
 @SP
 A=M
 M=D
 @SP
 M=M+1
 "#,
-                push_value_str
-            ),
-            "pop" => format!(
+                    push_value_str
+                )
+            }
+            (_, Some(address)) => format!(
                 r#"@SP
 M=M-1
 A=M
 D=M
-{}
-{}M=D
+@{}
+M=D
+"#,
+                address
+            ),
+            // Keeps the target address in R13 while popping into D
+            (_, None) => format!(
+                r#"@{}
+D=A
+@{}
+D=D+M
+@R13
+M=D
+@SP
+M=M-1
+A=M
+D=M
+@R13
+A=M
+M=D
 "#,
-                segment_str, index_str
+                index, base
             ),
-            _ => String::from(""),
         };
-        self.write(&translated_code);
+        self.write(&translated_code)
     }
 
-    pub fn write_arithmetic(&mut self, operator: &str) {
+    pub fn write_arithmetic(&mut self, operator: ArithOp) -> BoxResult<()> {
+        if self.compact {
+            if let Some(routine) = match operator {
+                ArithOp::Eq => Some("EQ"),
+                ArithOp::Gt => Some("GT"),
+                ArithOp::Lt => Some("LT"),
+                _ => None,
+            } {
+                // Jumps to the shared comparison with the return address in D
+                let current_count = self.operator_count.entry(operator).or_insert(0);
+                let return_label = format!("VM${}_RET{}", routine, current_count);
+                *current_count += 1;
+                self.routines.insert(routine);
+                return self.write(&format!(
+                    r#"@{label}
+D=A
+@VM${}
+0;JMP
+({label})
+"#,
+                    routine,
+                    label = return_label
+                ));
+            }
+        }
         // Gets the element on top of the stack and stores it temporarily with @0; A=M and D=M (M[address])
         // (+|-|eq|gt|lt|and|or) adds the topmost element to the second element from the top at M[address]
         // Writes the result back to the current stack top
@@ -130,19 +373,19 @@ D=M
 "#;
         // if not unary operator (neg and not), get second operand
         let mut get_second = "";
-        if operator != "neg" && operator != "not" {
+        if !operator.is_unary() {
             get_second = r#"@SP
 M=M-1
 A=M
 "#;
         }
 
-        let current_count = self.operator_count.entry(operator.to_string()).or_insert(0);
+        let current_count = self.operator_count.entry(operator).or_insert(0);
         let operation = match operator {
-            "add" => String::from("D=D+M"),
-            "sub" => String::from("D=M-D"),
-            "neg" => String::from("D=-D"),
-            "eq" => format!(
+            ArithOp::Add => String::from("D=D+M"),
+            ArithOp::Sub => String::from("D=M-D"),
+            ArithOp::Neg => String::from("D=-D"),
+            ArithOp::Eq => format!(
                 r#"D=M-D
 @EQ_TRUE_{n}
 D;JEQ
@@ -154,194 +397,580 @@ D=-1
 (EQ_RESULT_{n})"#,
                 n = current_count
             ),
-            "gt" => format!(
-                r#"D=M-D
-@GT_TRUE_{n}
-D;JGT
-D=0
-@GT_RESULT_{n}
-0;JMP
-(GT_TRUE_{n})
-D=-1
-(GT_RESULT_{n})"#,
-                n = current_count
-            ),
-            "lt" => format!(
-                r#"D=M-D
-@LT_TRUE_{n}
-D;JLT
+            ArithOp::Gt | ArithOp::Lt => {
+                // y is in D and x at the stack top, which is where SP points
+                let prefix = operator.name().to_uppercase();
+                let suffix = format!("_{}", current_count);
+                format!(
+                    r#"@R13
+M=D
+{}({p}_FALSE{s})
 D=0
-@LT_RESULT_{n}
+@{p}_RESULT{s}
 0;JMP
-(LT_TRUE_{n})
+({p}_TRUE{s})
 D=-1
-(LT_RESULT_{n})"#,
-                n = current_count
-            ),
-            "and" => String::from("D=D&M"),
-            "or" => String::from("D=D|M"),
-            "not" => String::from("D=!D"),
-            _ => String::from(""),
+({p}_RESULT{s})"#,
+                    signed_compare_code(operator, &prefix, &suffix, "@SP\nA=M", "R13"),
+                    p = prefix,
+                    s = suffix
+                )
+            }
+            ArithOp::And => String::from("D=D&M"),
+            ArithOp::Or => String::from("D=D|M"),
+            ArithOp::Not => String::from("D=!D"),
         };
         // increase operator counter for given operator
         *current_count += 1;
 
-        let writeAndIncr = r#"@SP
+        let write_and_incr = r#"@SP
 A=M
 M=D
 @SP
 M=M+1
 "#;
 
-        let translated_code = format!("{}{}{}\n{}", get_first, get_second, operation, writeAndIncr);
+        let translated_code = format!("{}{}{}\n{}", get_first, get_second, operation, write_and_incr);
 
-        self.write(&translated_code);
+        self.write(&translated_code)
     }
 
-    pub fn write_label(&mut self, label_name: &str) {
-        self.write("");
+    // labels are global in assembly, so a label inside a function becomes functionName$label
+    fn scoped_label(&self, label_name: &str) -> String {
+        if self.function_name.is_empty() {
+            label_name.to_string()
+        } else {
+            format!("{}${}", self.function_name, label_name)
+        }
     }
 
-    pub fn write_goto(&mut self, label_name: &str) {
-        self.write("");
+    pub fn write_init(&mut self) -> BoxResult<()> {
+        // Bootstrap code: points the stack at RAM 256 and calls Sys.init
+        self.write(
+            r#"@256
+D=A
+@SP
+M=D
+"#,
+        )?;
+        self.bootstrapped = true;
+        self.write_call("Sys.init", 0)
     }
 
-    pub fn write_if(&mut self, label_name: &str) {
-        self.write("");
+    pub fn write_label(&mut self, label_name: &str) -> BoxResult<()> {
+        let translated_code = format!("({})\n", self.scoped_label(label_name));
+        self.write(&translated_code)
     }
 
-    pub fn write_function(&mut self, function_name: &str, no_args: u8) {
-        self.write("");
+    pub fn write_goto(&mut self, label_name: &str) -> BoxResult<()> {
+        let translated_code = format!(
+            r#"@{}
+0;JMP
+"#,
+            self.scoped_label(label_name)
+        );
+        self.write(&translated_code)
     }
 
-    pub fn write_call(&mut self, function_name: &str, no_args: u8) {
-        self.write("");
+    pub fn write_if(&mut self, label_name: &str) -> BoxResult<()> {
+        // Pops the topmost element and jumps if it is not false (0)
+        let translated_code = format!(
+            r#"@SP
+M=M-1
+A=M
+D=M
+@{}
+D;JNE
+"#,
+            self.scoped_label(label_name)
+        );
+        self.write(&translated_code)
     }
 
-    pub fn write_return(&mut self) {
-        self.write("");
+    pub fn write_function(&mut self, function_name: &str, no_locals: u16) -> BoxResult<()> {
+        // Declares the entry label and initializes the k locals to 0
+        self.function_name = function_name.to_string();
+        self.functions.insert(function_name.to_string());
+        let push_zero = r#"@SP
+A=M
+M=0
+@SP
+M=M+1
+"#;
+        let translated_code = format!(
+            "({})\n{}",
+            function_name,
+            push_zero.repeat(no_locals as usize)
+        );
+        self.write(&translated_code)
     }
 
-    pub fn close(&mut self) {
+    pub fn write_call(&mut self, function_name: &str, no_args: u16) -> BoxResult<()> {
+        // Pushes the return address and the caller's LCL, ARG, THIS and THAT,
+        // repositions ARG to the first argument and LCL to the stack top
+        // and jumps to the callee, returning to `caller$ret.i`.
+        let caller = if self.function_name.is_empty() {
+            function_name
+        } else {
+            &self.function_name
+        };
+        let return_label = format!("{}$ret.{}", caller, self.call_count);
+        self.call_count += 1;
+
+        if self.compact {
+            // Passes the return address in R13, ARG's distance from the
+            // stack top in R14 and the callee in D to the shared call
+            self.routines.insert("CALL");
+            return self.write(&format!(
+                r#"@{label}
+D=A
+@R13
+M=D
+@{}
+D=A
+@R14
+M=D
+@{}
+D=A
+@VM$CALL
+0;JMP
+({label})
+"#,
+                5 + no_args as usize,
+                function_name,
+                label = return_label
+            ));
+        }
+
+        let mut translated_code = format!(
+            r#"@{}
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+"#,
+            return_label
+        );
+        translated_code.push_str(&push_frame_code());
+        translated_code.push_str(&format!(
+            r#"@SP
+D=M
+@{}
+D=D-A
+@ARG
+M=D
+@SP
+D=M
+@LCL
+M=D
+@{}
+0;JMP
+({})
+"#,
+            5 + no_args as usize,
+            function_name,
+            return_label
+        ));
+        self.write(&translated_code)
+    }
+
+    pub fn write_return(&mut self) -> BoxResult<()> {
+        if self.compact {
+            self.routines.insert("RETURN");
+            return self.write("@VM$RETURN\n0;JMP\n");
+        }
+        self.write(&return_code())
+    }
+
+    pub fn static_count(&self) -> usize {
+        self.statics.len()
+    }
+
+    /// Fails if the statics translated so far don't fit in RAM 16 to 255.
+    pub fn check_statics(&self) -> BoxResult<()> {
+        if self.statics.len() > MAX_STATICS {
+            bail!(
+                "{} static variables don't fit in RAM 16-255, the limit is {}.",
+                self.statics.len(),
+                MAX_STATICS
+            );
+        }
+        Ok(())
+    }
+
+    /// Fails if the bootstrap code calls Sys.init but no translated file
+    /// defines it, as the assembler would take `@Sys.init` for a variable.
+    pub fn check_init(&self) -> BoxResult<()> {
+        if self.bootstrapped && !self.functions.contains("Sys.init") {
+            bail!("The bootstrap code calls Sys.init, which no file defines.");
+        }
+        Ok(())
+    }
+
+    /// Writes the shared subroutines used in compact mode and flushes the
+    /// output.
+    pub fn close(&mut self) -> BoxResult<()> {
+        if !self.routines.is_empty() {
+            // the subroutines follow the program, which mustn't run into them
+            let mut translated_code = String::from("(VM$END)\n@VM$END\n0;JMP\n");
+            for routine in &self.routines {
+                translated_code.push_str(&routine_code(routine));
+            }
+            self.routines.clear();
+            self.write(&translated_code)?;
+        }
+        self.out_writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
         self.out_writer
-            .flush()
-            .expect("Unable to flush line writer");
     }
 }
 
-pub struct Parser<'a> {
-    name: &'a str,
-    cursor: Cursor<String>,
-    length: u64,
-    code_writer: &'a mut CodeWriter,
+// Pushes the caller's LCL, ARG, THIS and THAT
+fn push_frame_code() -> String {
+    let mut translated_code = String::new();
+    for segment in ["LCL", "ARG", "THIS", "THAT"].iter() {
+        translated_code.push_str(&format!(
+            r#"@{}
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+"#,
+            segment
+        ));
+    }
+    translated_code
 }
 
-impl<'a> Parser<'a> {
-    pub fn new(name: &'a str, input: &str, code_writer: &'a mut CodeWriter) -> Self {
-        let content = input
+fn return_code() -> String {
+    // Keeps the frame in R13 and the return address in R14, since the
+    // return value may overwrite it when there are no arguments,
+    // moves the return value to the caller's stack top
+    // and restores the caller's THAT, THIS, ARG and LCL.
+    let mut translated_code = String::from(
+        r#"@LCL
+D=M
+@R13
+M=D
+@5
+A=D-A
+D=M
+@R14
+M=D
+@SP
+M=M-1
+A=M
+D=M
+@ARG
+A=M
+M=D
+@ARG
+D=M+1
+@SP
+M=D
+"#,
+    );
+    for segment in ["THAT", "THIS", "ARG", "LCL"].iter() {
+        translated_code.push_str(&format!(
+            r#"@R13
+AM=M-1
+D=M
+@{}
+M=D
+"#,
+            segment
+        ));
+    }
+    translated_code.push_str(
+        r#"@R14
+A=M
+0;JMP
+"#,
+    );
+    translated_code
+}
+
+// Jumps to `{prefix}_TRUE{suffix}` if x > y for gt or x < y for lt and
+// falls through otherwise. `load_x` points A at x and the register `y` holds
+// y. Operands of different signs are told apart by their signs alone, since
+// x - y may overflow.
+fn signed_compare_code(
+    operator: ArithOp,
+    prefix: &str,
+    suffix: &str,
+    load_x: &str,
+    y: &str,
+) -> String {
+    let label = |kind: &str| format!("{}_{}{}", prefix, kind, suffix);
+    // the answer for x >= 0 > y, the opposite one for y >= 0 > x
+    let (x_positive, x_negative, jump) = match operator {
+        ArithOp::Gt => ("TRUE", "FALSE", "JGT"),
+        _ => ("FALSE", "TRUE", "JLT"),
+    };
+    format!(
+        r#"{load_x}
+D=M
+@{x_neg}
+D;JLT
+@{y}
+D=M
+@{when_x_positive}
+D;JLT
+@{same}
+0;JMP
+({x_neg})
+@{y}
+D=M
+@{when_x_negative}
+D;JGE
+({same})
+@{y}
+D=M
+{load_x}
+D=M-D
+@{true_label}
+D;{jump}
+"#,
+        load_x = load_x,
+        y = y,
+        x_neg = label("XNEG"),
+        same = label("SAME"),
+        when_x_positive = label(x_positive),
+        when_x_negative = label(x_negative),
+        true_label = label("TRUE"),
+        jump = jump
+    )
+}
+
+// The shared subroutines of compact mode. Labels starting with `VM$` are
+// free, as VM labels are scoped to functions named Class.function.
+fn routine_code(routine: &str) -> String {
+    match routine {
+        "GT" | "LT" => {
+            let operator = if routine == "GT" { ArithOp::Gt } else { ArithOp::Lt };
+            let prefix = format!("VM${}", routine);
+            format!(
+                r#"({p})
+@R13
+M=D
+@SP
+AM=M-1
+D=M
+@R14
+M=D
+{}({p}_FALSE)
+@SP
+A=M-1
+M=0
+@R13
+A=M
+0;JMP
+({p}_TRUE)
+@SP
+A=M-1
+M=-1
+@R13
+A=M
+0;JMP
+"#,
+                signed_compare_code(operator, &prefix, "", "@SP\nA=M-1", "R14"),
+                p = prefix
+            )
+        }
+        "EQ" => format!(
+            r#"(VM${name})
+@R13
+M=D
+@SP
+AM=M-1
+D=M
+A=A-1
+D=M-D
+M=-1
+@VM${name}_TRUE
+D;J{name}
+@SP
+A=M-1
+M=0
+(VM${name}_TRUE)
+@R13
+A=M
+0;JMP
+"#,
+            name = routine
+        ),
+        "CALL" => format!(
+            r#"(VM$CALL)
+@R15
+M=D
+@R13
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+{}@SP
+D=M
+@R14
+D=D-M
+@ARG
+M=D
+@SP
+D=M
+@LCL
+M=D
+@R15
+A=M
+0;JMP
+"#,
+            push_frame_code()
+        ),
+        "RETURN" => format!("(VM$RETURN)\n{}", return_code()),
+        _ => String::new(),
+    }
+}
+
+/// Splits VM source into commands, keeping their line numbers.
+pub struct Parser {
+    // the file name diagnostics refer to
+    filename: String,
+    // the lines holding commands without comments, numbered from 1
+    lines: Vec<(usize, String)>,
+}
+
+impl Parser {
+    pub fn new(filename: &str, input: &str) -> Self {
+        let lines = input
             .lines()
-            .filter(|line| !line.is_empty() && !line.starts_with("//"))
-            .fold(String::new(), |acc, line| acc + line + "\n");
-        let length = content.len() as u64;
+            .enumerate()
+            .map(|(i, line)| {
+                let code = line.split("//").next().unwrap_or("");
+                (i + 1, code.trim().to_string())
+            })
+            .filter(|(_, line)| !line.is_empty())
+            .collect();
         Self {
-            name,
-            cursor: Cursor::new(content),
-            length,
-            code_writer,
+            filename: filename.to_string(),
+            lines,
         }
     }
 
-    fn has_more_commands(&self) -> bool {
-        self.cursor.position() < self.length
+    /// Parses every line, failing with one `file:line: message` diagnostic
+    /// per invalid line.
+    pub fn parse(&self) -> BoxResult<Vec<(usize, VmCommand)>> {
+        let mut commands = Vec::new();
+        let mut diagnostics = Vec::new();
+        for (line, command) in &self.lines {
+            match Parser::parse_command(command) {
+                Ok(command) => commands.push((*line, command)),
+                Err(err) => diagnostics.push(format!("{}:{}: {}", self.filename, line, err)),
+            }
+        }
+        if !diagnostics.is_empty() {
+            bail!(diagnostics.join("\n"));
+        }
+        Ok(commands)
     }
 
-    fn read_next(&mut self) -> Result<String, &str> {
-        let mut buffer = String::new();
-        match self.cursor.read_line(&mut buffer) {
-            Ok(_) => Ok(buffer),
-            Err(_) => Err("Failed reading the next command."),
+    /// Parses a single command, its tokens separated by whitespace.
+    pub fn parse_command(command: &str) -> BoxResult<VmCommand> {
+        let tokens: Vec<&str> = command.split_whitespace().collect();
+        let keyword = match tokens.first() {
+            Some(keyword) => *keyword,
+            None => bail!("expected a command"),
+        };
+        let expected = match keyword {
+            "push" | "pop" | "function" | "call" => 3,
+            "label" | "goto" | "if-goto" => 2,
+            "return" => 1,
+            _ if ArithOp::from_name(keyword).is_some() => 1,
+            _ => bail!("unknown command `{}`", keyword),
+        };
+        if tokens.len() != expected {
+            bail!(
+                "`{}` takes {} argument(s), found {}",
+                keyword,
+                expected - 1,
+                tokens.len() - 1
+            );
         }
-    }
 
-    pub fn to_assembler(&mut self) -> Result<(), Box<dyn Error>> {
-        while self.has_more_commands() {
-            let command = self.read_next().unwrap_or_else(|err| err.to_string());
-            let parsed = match Parser::command_type(&command) {
-                Ok(Command::PUSHPOP) => {
-                    let regex = RE_PUSHPOP.captures(&command).unwrap();
-                    let command_type = regex.name("command").unwrap();
-                    let segment = regex.name("segment").unwrap();
-                    let index = regex.name("index").unwrap();
-                    self.code_writer.write_push_pop(
-                        self.name,
-                        command_type.as_str(),
-                        segment.as_str(),
-                        index.as_str().parse::<usize>().unwrap(),
-                    );
-                }
-                Ok(Command::ARITHMETIC) => {
-                    let regex = RE_ARITHMETIC.captures(&command).unwrap();
-                    let operator = regex.name("op").unwrap();
-                    self.code_writer.write_arithmetic(operator.as_str());
-                }
-                Ok(Command::LABEL) => {
-                    let regex = RE_LABEL.captures(&command).unwrap();
-                    let label_name = regex.name("name").unwrap();
-                    self.code_writer.write_label(label_name.as_str());
-                }
-                Ok(Command::GOTO) => {
-                    let regex = RE_GOTO.captures(&command).unwrap();
-                    let label_name = regex.name("name").unwrap();
-                    self.code_writer.write_goto(label_name.as_str());
-                }
-                Ok(Command::IF) => {
-                    let regex = RE_IF.captures(&command).unwrap();
-                    let label_name = regex.name("name").unwrap();
-                    self.code_writer.write_if(label_name.as_str());
-                }
-                Ok(Command::FUNCTION) => {
-                    let regex = RE_FUNCTION.captures(&command).unwrap();
-                    let function_name = regex.name("name").unwrap();
-                    let no_args = regex.name("k").unwrap();
-                    self.code_writer.write_call(function_name.as_str(), no_args.as_str().parse::<u8>().unwrap());
+        let parsed = match keyword {
+            "push" | "pop" => {
+                let segment = match Segment::from_name(tokens[1]) {
+                    Some(segment) => segment,
+                    None => bail!("unknown segment `{}`", tokens[1]),
+                };
+                let index = Parser::number(tokens[2])?;
+                match segment {
+                    Segment::Pointer if index > 1 => {
+                        bail!("pointer {} is out of range 0..1", index)
+                    }
+                    Segment::Temp if index > 7 => bail!("temp {} is out of range 0..7", index),
+                    Segment::Constant if keyword == "pop" => bail!("can't pop to constant"),
+                    Segment::Constant if index > MAX_CONSTANT => {
+                        bail!("constant {} is out of range 0..{}", index, MAX_CONSTANT)
+                    }
+                    _ => {}
                 }
-                Ok(Command::CALL) => {
-                    let regex = RE_CALL.captures(&command).unwrap();
-                    let function_name = regex.name("name").unwrap();
-                    let no_args = regex.name("n").unwrap();
-                    self.code_writer.write_call(function_name.as_str(), no_args.as_str().parse::<u8>().unwrap());
+                if keyword == "push" {
+                    VmCommand::Push(segment, index)
+                } else {
+                    VmCommand::Pop(segment, index)
                 }
-                Ok(Command::RETURN) => {
-                    let regex = RE_RETURN.captures(&command).unwrap();
-                    self.code_writer.write_return();
-                }
-                Err(e) => return Err(e),
-            };
-            // bytes.push_str(&parsed.unwrap());
-            // bytes.push_str("\n");
+            }
+            "label" => VmCommand::Label(Parser::symbol(tokens[1])?),
+            "goto" => VmCommand::Goto(Parser::symbol(tokens[1])?),
+            "if-goto" => VmCommand::IfGoto(Parser::symbol(tokens[1])?),
+            "function" => {
+                VmCommand::Function(Parser::symbol(tokens[1])?, Parser::number(tokens[2])?)
+            }
+            "call" => VmCommand::Call(Parser::symbol(tokens[1])?, Parser::number(tokens[2])?),
+            "return" => VmCommand::Return,
+            _ => VmCommand::Arithmetic(ArithOp::from_name(keyword).unwrap()),
+        };
+        Ok(parsed)
+    }
+
+    fn number(token: &str) -> BoxResult<u16> {
+        if !token.is_empty() && token.chars().all(|c| c.is_ascii_digit()) {
+            if let Ok(number) = token.parse::<u16>() {
+                return Ok(number);
+            }
         }
-        Ok(())
+        bail!("invalid number `{}`", token)
     }
 
-    pub fn command_type(command: &str) -> BoxResult<Command> {
-        match command {
-            command if RE_ARITHMETIC.is_match(command) => Ok(Command::ARITHMETIC),
-            command if RE_PUSHPOP.is_match(command) => Ok(Command::PUSHPOP),
-            command if RE_LABEL.is_match(command) => Ok(Command::LABEL),
-            command if RE_GOTO.is_match(command) => Ok(Command::GOTO),
-            command if RE_IF.is_match(command) => Ok(Command::IF),
-            command if RE_FUNCTION.is_match(command) => Ok(Command::FUNCTION),
-            command if RE_RETURN.is_match(command) => Ok(Command::RETURN),
-            command if RE_CALL.is_match(command) => Ok(Command::CALL),
-            _ => bail!("Invalid input."),
+    // a symbol is letters, digits, '_', '.' and ':', not starting with a digit
+    fn symbol(token: &str) -> BoxResult<String> {
+        let valid = token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == ':')
+            && !token.starts_with(|c: char| c.is_ascii_digit());
+        if !valid {
+            bail!("invalid symbol `{}`", token);
         }
+        Ok(token.to_string())
+    }
+
+    pub fn command_type(command: &str) -> BoxResult<Command> {
+        Ok(Parser::parse_command(command)?.command_type())
     }
 }
 
-impl fmt::Display for Parser<'_> {
+impl fmt::Display for Parser {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let string = self.cursor.clone().into_inner();
-        fmt.write_str(&string)?;
+        for (_, line) in &self.lines {
+            writeln!(fmt, "{}", line)?;
+        }
         Ok(())
     }
 }
@@ -358,11 +987,18 @@ pub fn write_file(filename: &str, content: &str) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub fn parse_input(args: &[String]) -> Result<&str, &str> {
-    if args.len() < 2 {
-        return Err("not enough arguments");
+/// The `.vm` files of a directory, sorted by name so the output is stable.
+pub fn vm_files(dir: &Path) -> BoxResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "vm") {
+            files.push(path);
+        }
     }
-    let filename = &args[1];
-
-    Ok(filename)
+    files.sort();
+    if files.is_empty() {
+        bail!("No .vm files in {}.", dir.display());
+    }
+    Ok(files)
 }
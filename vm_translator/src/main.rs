@@ -1,34 +1,124 @@
 use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process;
-use std::path::Path;
 
-use vm_translator::{Parser, CodeWriter};
+use vm_translator::{CodeWriter, Parser};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    let filename = vm_translator::parse_input(&args).unwrap_or_else(|err| {
-        println!("Problem parsing arguments: {}", err);
-        process::exit(1);
-    });
+const USAGE: &str =
+    "Usage: vm_translator FILE.vm|DIRECTORY [--bootstrap|--no-bootstrap] [--compact] [--stdout]";
 
-    // TODO: add implementation for directory with multiple files
-    let out_filename = filename.replace(".vm", ".asm");
-    let out_filepath = Path::new(&out_filename);
+struct Options {
+    input: String,
+    /// None writes the bootstrap code for directories only.
+    bootstrap: Option<bool>,
+    compact: bool,
+    stdout: bool,
+}
 
-    let mut code_writer = CodeWriter::new(out_filepath);
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        input: String::new(),
+        bootstrap: None,
+        compact: false,
+        stdout: false,
+    };
+    for arg in args.iter().skip(1) {
+        match arg.as_str() {
+            "--bootstrap" => options.bootstrap = Some(true),
+            "--no-bootstrap" => options.bootstrap = Some(false),
+            "--compact" => options.compact = true,
+            "--stdout" => options.stdout = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => options.input = arg.clone(),
+        }
+    }
+    if options.input.is_empty() {
+        return Err(String::from("not enough arguments"));
+    }
+    Ok(options)
+}
 
-    let content = vm_translator::read_file(filename).unwrap_or_else(|err| {
-        println!("Application error: {}", err);
+fn fail(context: &str, err: impl std::fmt::Display) -> ! {
+    eprintln!("{}: {}", context, err);
+    process::exit(1);
+}
 
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_options(&args).unwrap_or_else(|err| {
+        eprintln!("Problem parsing arguments: {}", err);
+        eprintln!("{}", USAGE);
         process::exit(1);
     });
 
-    println!("{}", content);
+    // a directory translates into Dir/Dir.asm, a file into File.asm next to it
+    let input = Path::new(&options.input);
+    let (files, out_filepath) = if input.is_dir() {
+        let files =
+            vm_translator::vm_files(input).unwrap_or_else(|err| fail("Application error", err));
+        let name = input
+            .canonicalize()
+            .ok()
+            .and_then(|dir| dir.file_name().map(|name| name.to_os_string()))
+            .unwrap_or_else(|| fail("Application error", "invalid directory name"));
+        let out_filepath = input.join(name).with_extension("asm");
+        (files, out_filepath)
+    } else {
+        (vec![PathBuf::from(input)], input.with_extension("asm"))
+    };
+
+    // parse everything first, so that bad input leaves no output file behind
+    let programs: Vec<_> = files
+        .iter()
+        .map(|file| {
+            let content = vm_translator::read_file(&file.to_string_lossy())
+                .unwrap_or_else(|err| fail("Application error", err));
+            // each file keeps its own static namespace
+            let name = file
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let commands = Parser::new(&file.to_string_lossy(), &content)
+                .parse()
+                .unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                });
+            (file, name, commands)
+        })
+        .collect();
 
-    let name = filename.split("/").last().unwrap().replace(".vm", "");
-    let mut parser = Parser::new(&name, &content, &mut code_writer);
-    parser.to_assembler();
+    let out: Box<dyn Write> = if options.stdout {
+        Box::new(io::stdout())
+    } else {
+        let file = File::create(&out_filepath).unwrap_or_else(|err| {
+            fail(&format!("Failed creating {}", out_filepath.display()), err)
+        });
+        Box::new(file)
+    };
+    let mut code_writer = CodeWriter::new(BufWriter::new(out));
+    code_writer.set_compact(options.compact);
+    // like the course tools, only whole programs get the bootstrap code
+    if options.bootstrap.unwrap_or_else(|| input.is_dir()) {
+        if let Err(err) = code_writer.write_init() {
+            fail("Failed writing", err);
+        }
+    }
+    for (file, name, commands) in &programs {
+        code_writer
+            .write_commands(name, commands)
+            .unwrap_or_else(|err| fail(&format!("Failed translating {}", file.display()), err));
+    }
 
-    code_writer.close();
+    if let Err(err) = code_writer
+        .check_statics()
+        .and_then(|_| code_writer.check_init())
+    {
+        fail("Application error", err);
+    }
+    if let Err(err) = code_writer.close() {
+        fail("Failed writing", err);
+    }
 }
@@ -1,8 +1,11 @@
 use vm_translator::Parser;
 use vm_translator::CodeWriter;
 use vm_translator::Command;
-use vm_translator::read_file;
-use tempfile::NamedTempFile;
+use vm_translator::{ArithOp, Segment, VmCommand};
+use vm_translator::vm_files;
+use vm_translator::MAX_STATICS;
+use std::fs;
+use hack_emulator::Computer;
 
 const SIMPLE_ADD_INPUT: &str = r#"
 // This file is part of www.nand2tetris.org
@@ -17,15 +20,21 @@ add
 
 "#;
 
+/// Translates the input as the file `test` and returns the assembly.
+fn translate(input: &str) -> String {
+    let mut cw = CodeWriter::new(Vec::new());
+    let commands = Parser::new("test.vm", input).parse().unwrap();
+    cw.write_commands("test", &commands).unwrap();
+    String::from_utf8(cw.into_inner()).unwrap()
+}
+
 #[test]
 fn test_init_parser() {
     let stripped_input1 = r#"push constant 7
 push constant 8
 add
 "#;
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let parser = Parser::new("test", SIMPLE_ADD_INPUT, &mut cw);
+    let parser = Parser::new("test.vm", SIMPLE_ADD_INPUT);
     assert_eq!(stripped_input1, parser.to_string());
 }
 
@@ -78,6 +87,27 @@ fn test_command_parser() {
     );
 }
 
+#[test]
+fn test_parse() {
+    let commands = Parser::new("test.vm", SIMPLE_ADD_INPUT).parse().unwrap();
+    assert_eq!(
+        commands,
+        vec![
+            (8, VmCommand::Push(Segment::Constant, 7)),
+            (9, VmCommand::Push(Segment::Constant, 8)),
+            (10, VmCommand::Arithmetic(ArithOp::Add)),
+        ]
+    );
+
+    let input = "function Main.main 2\npop that 3\nif-goto END\ncall Foo.bar 1\nreturn\n";
+    let commands = Parser::new("test.vm", input).parse().unwrap();
+    let printed: Vec<String> = commands.iter().map(|(_, command)| command.to_string()).collect();
+    assert_eq!(printed.join("\n") + "\n", input);
+
+    let err = Parser::new("test.vm", "add\n\nfoo 3\n").parse().unwrap_err();
+    assert_eq!(err.to_string(), "test.vm:3: unknown command `foo`");
+}
+
 #[test]
 fn test_push() {
     let input: &str = r#"push constant 7
@@ -92,11 +122,7 @@ M=D
 M=M+1
 "#;
 
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let mut parser = Parser::new("test", input, &mut cw);
-    parser.to_assembler();
-    let output = read_file(file.path().to_str().unwrap()).unwrap();
+    let output = translate(input);
     assert_eq!(output, expected_output);
 }
 
@@ -136,11 +162,7 @@ M=D
 M=M+1
 "#;
 
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let mut parser = Parser::new("test", input, &mut cw);
-    parser.to_assembler();
-    let output = read_file(file.path().to_str().unwrap()).unwrap();
+    let output = translate(input);
     assert_eq!(output, expected_output);
 }
 
@@ -180,11 +202,7 @@ M=D
 M=M+1
 "#;
 
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let mut parser = Parser::new("test", input, &mut cw);
-    parser.to_assembler();
-    let output = read_file(file.path().to_str().unwrap()).unwrap();
+    let output = translate(input);
     assert_eq!(output, expected_output);
 }
 
@@ -213,11 +231,7 @@ M=D
 M=M+1
 "#;
 
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let mut parser = Parser::new("test", input, &mut cw);
-    parser.to_assembler();
-    let output = read_file(file.path().to_str().unwrap()).unwrap();
+    let output = translate(input);
     assert_eq!(output, expected_output);
 }
 
@@ -265,11 +279,7 @@ M=D
 M=M+1
 "#;
 
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let mut parser = Parser::new("test", input, &mut cw);
-    parser.to_assembler();
-    let output = read_file(file.path().to_str().unwrap()).unwrap();
+    let output = translate(input);
     assert_eq!(output, expected_output);
 }
 
@@ -301,9 +311,33 @@ D=M
 @SP
 M=M-1
 A=M
+@R13
+M=D
+@SP
+A=M
+D=M
+@LT_XNEG_0
+D;JLT
+@R13
+D=M
+@LT_FALSE_0
+D;JLT
+@LT_SAME_0
+0;JMP
+(LT_XNEG_0)
+@R13
+D=M
+@LT_TRUE_0
+D;JGE
+(LT_SAME_0)
+@R13
+D=M
+@SP
+A=M
 D=M-D
 @LT_TRUE_0
 D;JLT
+(LT_FALSE_0)
 D=0
 @LT_RESULT_0
 0;JMP
@@ -317,11 +351,7 @@ M=D
 M=M+1
 "#;
 
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let mut parser = Parser::new("test", input, &mut cw);
-    parser.to_assembler();
-    let output = read_file(file.path().to_str().unwrap()).unwrap();
+    let output = translate(input);
     assert_eq!(output, expected_output);
 }
 
@@ -353,9 +383,33 @@ D=M
 @SP
 M=M-1
 A=M
+@R13
+M=D
+@SP
+A=M
+D=M
+@GT_XNEG_0
+D;JLT
+@R13
+D=M
+@GT_TRUE_0
+D;JLT
+@GT_SAME_0
+0;JMP
+(GT_XNEG_0)
+@R13
+D=M
+@GT_FALSE_0
+D;JGE
+(GT_SAME_0)
+@R13
+D=M
+@SP
+A=M
 D=M-D
 @GT_TRUE_0
 D;JGT
+(GT_FALSE_0)
 D=0
 @GT_RESULT_0
 0;JMP
@@ -369,11 +423,7 @@ M=D
 M=M+1
 "#;
 
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let mut parser = Parser::new("test", input, &mut cw);
-    parser.to_assembler();
-    let output = read_file(file.path().to_str().unwrap()).unwrap();
+    let output = translate(input);
     assert_eq!(output, expected_output);
 }
 
@@ -413,11 +463,7 @@ M=D
 M=M+1
 "#;
 
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let mut parser = Parser::new("test", input, &mut cw);
-    parser.to_assembler();
-    let output = read_file(file.path().to_str().unwrap()).unwrap();
+    let output = translate(input);
     assert_eq!(output, expected_output);
 }
 
@@ -457,11 +503,7 @@ M=D
 M=M+1
 "#;
 
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let mut parser = Parser::new("test", input, &mut cw);
-    parser.to_assembler();
-    let output = read_file(file.path().to_str().unwrap()).unwrap();
+    let output = translate(input);
     assert_eq!(output, expected_output);
 }
 
@@ -490,11 +532,7 @@ M=D
 M=M+1
 "#;
 
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let mut parser = Parser::new("test", input, &mut cw);
-    parser.to_assembler();
-    let output = read_file(file.path().to_str().unwrap()).unwrap();
+    let output = translate(input);
     assert_eq!(output, expected_output);
 }
 
@@ -511,19 +549,489 @@ A=M
 M=D
 @SP
 M=M+1
+@0
+D=A
+@LCL
+D=D+M
+@R13
+M=D
 @SP
 M=M-1
 A=M
 D=M
-@LCL
+@R13
+A=M
+M=D
+"#;
+
+    let output = translate(input);
+    assert_eq!(output, expected_output);
+}
+
+#[test]
+fn test_label_goto_if() {
+    let input: &str = r#"label LOOP_START
+push constant 0
+if-goto LOOP_START
+goto END.PROGRAM:1
+"#;
+
+let expected_output: &str = r#"(LOOP_START)
+@0
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+D=M
+@LOOP_START
+D;JNE
+@END.PROGRAM:1
+0;JMP
+"#;
+
+    let output = translate(input);
+    assert_eq!(output, expected_output);
+    assert!(assembler::Parser::new(&output).to_bytes().is_ok());
+}
+
+#[test]
+fn test_label_scoped_to_function() {
+    let mut cw = CodeWriter::new(Vec::new());
+    cw.write_function("Main.loop", 0).unwrap();
+    cw.write_label("LOOP").unwrap();
+    cw.write_goto("LOOP").unwrap();
+    let output = String::from_utf8(cw.into_inner()).unwrap();
+    assert!(output.contains("(Main.loop$LOOP)\n"));
+    assert!(output.contains("@Main.loop$LOOP\n0;JMP\n"));
+}
+
+#[test]
+fn test_function() {
+    let input: &str = r#"function Foo.bar 2
+label LOOP
+"#;
+
+let expected_output: &str = r#"(Foo.bar)
+@SP
+A=M
+M=0
+@SP
+M=M+1
+@SP
+A=M
+M=0
+@SP
+M=M+1
+(Foo.bar$LOOP)
+"#;
+
+    let output = translate(input);
+    assert_eq!(output, expected_output);
+}
+
+#[test]
+fn test_call() {
+    let input: &str = r#"function Foo.bar 0
+call Foo.baz 2
+call Foo.baz 0
+"#;
+
+    let output = translate(input);
+    // the return addresses are unique and ARG is SP - 5 - nArgs
+    assert!(output.starts_with("(Foo.bar)\n@Foo.bar$ret.0\nD=A\n"));
+    assert!(output.contains("@SP\nD=M\n@7\nD=D-A\n@ARG\nM=D\n@SP\nD=M\n@LCL\nM=D\n@Foo.baz\n0;JMP\n(Foo.bar$ret.0)\n"));
+    assert!(output.contains("@5\nD=D-A\n@ARG\nM=D\n@SP\nD=M\n@LCL\nM=D\n@Foo.baz\n0;JMP\n(Foo.bar$ret.1)\n"));
+    for segment in ["LCL", "ARG", "THIS", "THAT"].iter() {
+        assert_eq!(output.matches(&format!("@{}\nD=M\n@SP\nA=M\nM=D\n", segment)).count(), 2);
+    }
+}
+
+#[test]
+fn test_return() {
+    let input: &str = "return\n";
+
+    let output = translate(input);
+    assert!(output.starts_with("@LCL\nD=M\n@R13\nM=D\n@5\nA=D-A\nD=M\n@R14\nM=D\n"));
+    assert!(output.contains("@ARG\nD=M+1\n@SP\nM=D\n"));
+    assert!(output.contains("@R13\nAM=M-1\nD=M\n@LCL\nM=D\n@R14\nA=M\n0;JMP\n"));
+}
+
+#[test]
+fn test_write_init() {
+    let mut cw = CodeWriter::new(Vec::new());
+    cw.write_init().unwrap();
+    let output = String::from_utf8(cw.into_inner()).unwrap();
+    assert!(output.starts_with("@256\nD=A\n@SP\nM=D\n@Sys.init$ret.0\n"));
+    assert!(output.ends_with("@Sys.init\n0;JMP\n(Sys.init$ret.0)\n"));
+}
+
+#[test]
+fn test_vm_files() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["Sys.vm", "Main.vm", "Main.asm", "notes.txt"].iter() {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+    let files = vm_files(dir.path()).unwrap();
+    assert_eq!(files, vec![dir.path().join("Main.vm"), dir.path().join("Sys.vm")]);
+
+    let empty = tempfile::tempdir().unwrap();
+    assert!(vm_files(empty.path()).is_err());
+}
+
+#[test]
+fn test_static() {
+    let input: &str = r#"push static 3
+pop static 3
+"#;
+
+let expected_output: &str = r#"@test.3
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+D=M
+@test.3
+M=D
+"#;
+
+    let mut cw = CodeWriter::new(Vec::new());
+    let commands = Parser::new("test.vm", input).parse().unwrap();
+    cw.write_commands("test", &commands).unwrap();
+    assert_eq!(cw.static_count(), 1);
+    let output = String::from_utf8(cw.into_inner()).unwrap();
+    assert_eq!(output, expected_output);
+}
+
+#[test]
+fn test_check_statics() {
+    let mut cw = CodeWriter::new(Vec::new());
+    for index in 0..MAX_STATICS {
+        cw.write_push_pop("Foo", true, Segment::Static, index as u16).unwrap();
+    }
+    assert!(cw.check_statics().is_ok());
+    cw.write_push_pop("Bar", false, Segment::Static, 0).unwrap();
+    assert!(cw.check_statics().is_err());
+}
+
+#[test]
+fn test_push_segment_offset() {
+    let input: &str = "push argument 40\n";
+
+let expected_output: &str = r#"@40
+D=A
+@ARG
+A=D+M
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+"#;
+
+    let output = translate(input);
+    assert_eq!(output, expected_output);
+}
+
+#[test]
+fn test_pointer_temp() {
+    let input: &str = r#"push pointer 1
+pop temp 7
+"#;
+
+let expected_output: &str = r#"@4
+D=M
+@SP
 A=M
 M=D
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+D=M
+@12
+M=D
 "#;
 
-    let file = NamedTempFile::new().unwrap();
-    let mut cw = CodeWriter::new(file.path());
-    let mut parser = Parser::new("test", input, &mut cw);
-    parser.to_assembler();
-    let output = read_file(file.path().to_str().unwrap()).unwrap();
+    let output = translate(input);
     assert_eq!(output, expected_output);
+
+    for input in ["push pointer 2\n", "pop temp 8\n", "pop constant 1\n"].iter() {
+        assert!(Parser::new("test.vm", input).parse().is_err());
+    }
+}
+
+#[test]
+fn test_strict_grammar() {
+    let input = "push constant 7 // seven\n  add\t// sum\nlabel a.b:c_1\n";
+    let commands = Parser::new("test.vm", input).parse().unwrap();
+    assert_eq!(
+        commands,
+        vec![
+            (1, VmCommand::Push(Segment::Constant, 7)),
+            (2, VmCommand::Arithmetic(ArithOp::Add)),
+            (3, VmCommand::Label(String::from("a.b:c_1"))),
+        ]
+    );
+
+    let errors = [
+        ("addfoo", "unknown command `addfoo`"),
+        ("pushXlocalY3", "unknown command `pushXlocalY3`"),
+        ("add 1", "`add` takes 0 argument(s), found 1"),
+        ("push local", "`push` takes 2 argument(s), found 1"),
+        ("push locals 3", "unknown segment `locals`"),
+        ("pop local -1", "invalid number `-1`"),
+        ("push constant 32768", "constant 32768 is out of range 0..32767"),
+        ("call Foo.bar 99999", "invalid number `99999`"),
+        ("function 1Foo 0", "invalid symbol `1Foo`"),
+        ("goto END$", "invalid symbol `END$`"),
+    ];
+    for (command, message) in errors.iter() {
+        let err = Parser::parse_command(command).unwrap_err();
+        assert_eq!(&err.to_string(), message);
+    }
+
+    let err = Parser::new("Foo.vm", "addfoo\nadd\nreturn 1\n").parse().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Foo.vm:1: unknown command `addfoo`\nFoo.vm:3: `return` takes 0 argument(s), found 1"
+    );
+}
+
+/// A sink whose writes always fail, like a full disk.
+struct FailingWriter;
+
+impl std::io::Write for FailingWriter {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::other("disk full"))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_write_error() {
+    let mut cw = CodeWriter::new(FailingWriter);
+    let commands = Parser::new("test.vm", "push constant 1\nadd\n").parse().unwrap();
+    let err = cw.write_commands("test", &commands).unwrap_err();
+    assert_eq!(err.to_string(), "line 1: disk full");
+    assert!(cw.write_init().is_err());
+}
+
+#[test]
+fn test_compact() {
+    let input: &str = r#"function Foo.bar 0
+eq
+eq
+lt
+call Foo.baz 1
+return
+"#;
+
+    let mut cw = CodeWriter::new(Vec::new());
+    cw.set_compact(true);
+    let commands = Parser::new("test.vm", input).parse().unwrap();
+    cw.write_commands("test", &commands).unwrap();
+    cw.close().unwrap();
+    let output = String::from_utf8(cw.into_inner()).unwrap();
+
+    assert!(output.starts_with(
+        "(Foo.bar)\n@VM$EQ_RET0\nD=A\n@VM$EQ\n0;JMP\n(VM$EQ_RET0)\n@VM$EQ_RET1\nD=A\n@VM$EQ\n0;JMP\n(VM$EQ_RET1)\n"
+    ));
+    assert!(output.contains(
+        "@Foo.bar$ret.0\nD=A\n@R13\nM=D\n@6\nD=A\n@R14\nM=D\n@Foo.baz\nD=A\n@VM$CALL\n0;JMP\n(Foo.bar$ret.0)\n@VM$RETURN\n0;JMP\n(VM$END)\n"
+    ));
+    // each subroutine is written once, only when used
+    for routine in ["(VM$EQ)", "(VM$LT)", "(VM$CALL)", "(VM$RETURN)"].iter() {
+        assert_eq!(output.matches(routine).count(), 1);
+    }
+    assert!(!output.contains("(VM$GT)"));
+    assert!(output.contains("@VM$LT_TRUE\nD;JLT\n"));
+}
+
+/// Translates, assembles and runs the input with the stack at 256 and
+/// returns the computer to inspect. The program ends in a loop, as the
+/// computer would start over past the end of the ROM.
+fn run(input: &str, compact: bool) -> Computer {
+    let mut cw = CodeWriter::new(Vec::new());
+    cw.set_compact(compact);
+    let input = format!("{}label END\ngoto END\n", input);
+    let commands = Parser::new("test.vm", &input).parse().unwrap();
+    cw.write_commands("test", &commands).unwrap();
+    cw.close().unwrap();
+    let asm = String::from_utf8(cw.into_inner()).unwrap();
+    let hack = assembler::Parser::new(&asm).to_bytes().unwrap();
+    let program = hack_emulator::parse_hack(&hack).unwrap();
+    let mut computer = Computer::from_program(&program);
+    computer.write(0, 256);
+    computer.run(200_000);
+    computer
+}
+
+/// VM code pushing any 16-bit value, as constants are 0..32767.
+fn push(value: i16) -> String {
+    match value {
+        i16::MIN => String::from("push constant 32767\nneg\npush constant 1\nsub\n"),
+        value if value < 0 => format!("push constant {}\nneg\n", -value),
+        value => format!("push constant {}\n", value),
+    }
+}
+
+#[test]
+fn test_comparisons_run() {
+    let values: [i16; 9] = [i16::MIN, -32767, -2, -1, 0, 1, 2, 32766, i16::MAX];
+    let mut input = String::new();
+    let mut expected = Vec::new();
+    for &x in values.iter() {
+        for &y in values.iter() {
+            for (op, result) in [("eq", x == y), ("gt", x > y), ("lt", x < y)].iter() {
+                input.push_str(&push(x));
+                input.push_str(&push(y));
+                input.push_str(op);
+                input.push('\n');
+                expected.push((x, y, *op, *result));
+            }
+        }
+    }
+
+    for &compact in [false, true].iter() {
+        let computer = run(&input, compact);
+        assert_eq!(computer.read(0) as usize, 256 + expected.len());
+        for (i, (x, y, op, result)) in expected.iter().enumerate() {
+            let value = computer.read(256 + i as u16);
+            let want = if *result { 0xffff } else { 0 };
+            assert_eq!(value, want, "{} {} {} (compact: {})", x, op, y, compact);
+        }
+    }
+}
+
+#[test]
+fn test_arithmetic_run() {
+    // add and sub wrap around, and the stack ends with the results only
+    let input = format!(
+        "{}{}add\n{}{}sub\n{}neg\n{}not\n{}{}and\n{}{}or\n",
+        push(32767),
+        push(1),
+        push(i16::MIN),
+        push(1),
+        push(i16::MIN),
+        push(0),
+        push(12),
+        push(10),
+        push(12),
+        push(10)
+    );
+    let computer = run(&input, false);
+    assert_eq!(computer.read(0), 262);
+    let results: Vec<u16> = (256..262).map(|address| computer.read(address)).collect();
+    assert_eq!(results, vec![0x8000, 0x7fff, 0x8000, 0xffff, 8, 14]);
+}
+
+#[test]
+fn test_labels_run() {
+    // labels with '.' and ':' assemble once scoped to their function
+    let input = r#"function Main.f 0
+push constant 3
+pop temp 0
+label LOOP.1
+push temp 0
+push constant 1
+sub
+pop temp 0
+push temp 0
+if-goto LOOP.1
+goto A:b
+push constant 99
+pop temp 1
+label A:b
+push constant 7
+pop temp 2
+"#;
+    for &compact in [false, true].iter() {
+        let computer = run(input, compact);
+        assert_eq!(computer.read(5), 0);
+        assert_eq!(computer.read(6), 0);
+        assert_eq!(computer.read(7), 7);
+        assert_eq!(computer.read(0), 256);
+    }
+}
+
+#[test]
+fn test_call_return_run() {
+    // Main.main's result lands in temp 0, the loop keeps it from running
+    // into the functions below
+    let input = r#"push constant 3000
+pop pointer 0
+push constant 4000
+pop pointer 1
+call Main.main 0
+pop temp 0
+label HALT
+goto HALT
+function Main.main 1
+push constant 5
+call Main.double 1
+pop local 0
+push constant 3
+call Main.addSeven 1
+push local 0
+add
+return
+function Main.double 0
+push constant 5000
+pop pointer 0
+push argument 0
+push argument 0
+add
+return
+function Main.addSeven 2
+push local 1
+call Main.seven 0
+add
+push argument 0
+add
+return
+function Main.seven 0
+push constant 7
+return
+"#;
+    for &compact in [false, true].iter() {
+        let computer = run(input, compact);
+        // double(5) + addSeven(3)
+        assert_eq!(20, computer.read(5));
+        assert_eq!(256, computer.read(0));
+        // LCL, ARG, THIS and THAT are back to the caller's
+        assert_eq!(
+            [0, 0, 3000, 4000],
+            [
+                computer.read(1),
+                computer.read(2),
+                computer.read(3),
+                computer.read(4)
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_check_init() {
+    let mut cw = CodeWriter::new(Vec::new());
+    assert!(cw.check_init().is_ok());
+    cw.write_init().unwrap();
+    assert!(cw.check_init().is_err());
+    cw.write_function("Sys.init", 0).unwrap();
+    assert!(cw.check_init().is_ok());
 }